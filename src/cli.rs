@@ -0,0 +1,942 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Read},
+    path::{Path, PathBuf},
+};
+
+use age::secrecy::{ExposeSecret, Secret};
+use anyhow::Result;
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand};
+use inquire::{Confirm, Text};
+use snafu::prelude::*;
+
+use crate::config::{Config, VaultConfig};
+use crate::ocra;
+use crate::storage::{
+    onboarding, parse_recipients, prompt_new_pass, write_atomic, ImageKind, Key, PassSource, StorageError, TeamMember,
+    DB, PASSPHRASE_ENV,
+};
+use crate::output::{current_code, Format, Record};
+use crate::search::search;
+use crate::tui;
+use crate::types::{decode_qr, Algo, Edit, SecretEncoding, OTP};
+
+#[derive(Debug, Snafu)]
+pub enum CliError {
+    #[snafu(display("No entry matches {query}"))]
+    NoMatch { query: String },
+    #[snafu(display("{query} matches several entries, be more specific: {candidates}"))]
+    Ambiguous { query: String, candidates: String },
+    #[snafu(display("{err}"))]
+    MissingInput { err: String },
+    #[snafu(display("{code} is not a valid code of {label}"))]
+    Rejected { code: String, label: String },
+    #[snafu(display("Cancelled"))]
+    Cancelled,
+}
+
+/// Exit status for an error, as listed in the help text.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    if let Some(err) = err.downcast_ref::<CliError>() {
+        return match err {
+            CliError::NoMatch { .. } | CliError::Ambiguous { .. } => 3,
+            CliError::MissingInput { .. } => 2,
+            CliError::Rejected { .. } | CliError::Cancelled => 1,
+        };
+    }
+    if matches!(err.downcast_ref::<StorageError>(), Some(StorageError::NoPassphrase { .. })) {
+        return 2;
+    }
+    let locked = matches!(err.downcast_ref::<StorageError>(), Some(StorageError::WrongKey { .. }))
+        || err.downcast_ref::<age::DecryptError>().is_some();
+    if locked {
+        4
+    } else {
+        1
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rotp",
+    version,
+    about = "Rusty Terminal OTP manager, showing every code full screen when run without a command",
+    after_help = "The password of a database is taken from --passphrase-fd, --passphrase-file or \
+                  --passphrase-cmd, then from the ROTP_PASSPHRASE variable, then from the security.passphrase_command \
+                  setting, and asked for otherwise. Databases encrypted to recipients are unlocked with --identity.\n\n\
+                  Exit status: 0 on success, 1 on errors, 2 on invalid usage or a missing password, 3 when no or \
+                  several entries match a query, 4 when the database could not be unlocked."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Database to use, instead of ROTP_DB or the configured default
+    #[arg(long, global = true)]
+    pub db: Option<PathBuf>,
+    /// Name of a registered vault to use, see `vaults list`
+    #[arg(long, global = true, conflicts_with = "db")]
+    pub vault: Option<String>,
+    /// age identity file or SSH private key unlocking a database encrypted
+    /// to recipients, may be given several times
+    #[arg(short, long, global = true)]
+    pub identity: Vec<PathBuf>,
+    /// Read the password from the first line of this open file descriptor
+    /// instead of asking for it, e.g. `--passphrase-fd 3 3<secret`
+    #[arg(long, global = true, conflicts_with_all = ["passphrase_file", "passphrase_cmd"])]
+    pub passphrase_fd: Option<i32>,
+    /// Read the password from the first line of this file instead of asking
+    /// for it
+    #[arg(long, global = true, conflicts_with = "passphrase_cmd")]
+    pub passphrase_file: Option<PathBuf>,
+    /// Read the password from the first line printed by this shell command
+    /// instead of asking for it, e.g. "pass show rotp"
+    #[arg(long, global = true)]
+    pub passphrase_cmd: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Create a new database and make it the default one
+    Init,
+    /// Add an entry from an otpauth:// URI, a QR code image or typed in values
+    #[command(group(ArgGroup::new("source").required(true).args(["uri", "qr", "manual"])))]
+    Add {
+        /// otpauth:// URI of the entry
+        uri: Option<String>,
+        /// Image of the QR code to scan, it is kept in the database
+        #[arg(long)]
+        qr: Option<PathBuf>,
+        /// Type in the issuer, account and secret of a TOTP entry
+        #[arg(long)]
+        manual: bool,
+        /// The typed in secret is hex instead of base32
+        #[arg(long, requires = "manual")]
+        hex: bool,
+        /// Logo of the issuer to keep with the entry
+        #[arg(long)]
+        icon: Option<PathBuf>,
+    },
+    /// List the entries
+    List {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Print the current code of an entry
+    Code {
+        /// Words fuzzily matching the issuer, account, tags or notes of the
+        /// entry, or its id
+        query: String,
+        /// Challenge to answer, for OCRA entries. The PIN is asked for when
+        /// the OCRA suite needs one
+        #[arg(long)]
+        challenge: Option<String>,
+        /// Hex encoded session information asked for by the OCRA suite
+        #[arg(long)]
+        session: Option<String>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Print everything stored about an entry
+    Show {
+        query: String,
+        /// Also write the QR code image the entry was added from to this
+        /// file, only readable by you
+        #[arg(long)]
+        qr: Option<PathBuf>,
+        /// Also write the logo kept with the entry to this file
+        #[arg(long)]
+        icon: Option<PathBuf>,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Check a code against an entry, e.g. one shown by another device
+    Verify {
+        query: String,
+        code: String,
+        /// Periods before and after the current one to accept, or counters
+        /// after the current one for HOTP entries
+        #[arg(long, default_value_t = 1)]
+        window: u64,
+    },
+    /// Remove an entry
+    Remove {
+        query: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Change the issuer or account of an entry
+    #[command(group(ArgGroup::new("label").required(true).multiple(true).args(["issuer", "account"])))]
+    Rename {
+        query: String,
+        #[arg(long)]
+        issuer: Option<String>,
+        #[arg(long)]
+        account: Option<String>,
+    },
+    /// Change the parameters of an entry
+    #[command(group(ArgGroup::new("change").required(true).multiple(true).args(["algorithm", "digits", "period", "counter", "tags", "notes"])))]
+    Edit {
+        query: String,
+        /// SHA1, SHA256 or SHA512
+        #[arg(long)]
+        algorithm: Option<Algo>,
+        #[arg(long)]
+        digits: Option<u32>,
+        /// Seconds a TOTP code is valid for
+        #[arg(long)]
+        period: Option<u32>,
+        /// Next counter of an HOTP or OCRA entry
+        #[arg(long)]
+        counter: Option<u64>,
+        /// Comma separated words to find the entry by, replacing the current
+        /// ones, "" removes them all
+        #[arg(long, value_delimiter = ',')]
+        tags: Option<Vec<String>>,
+        /// Free text to keep with the entry, "" removes it
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    /// Add the otpauth:// URIs listed in a file, one per line
+    Import {
+        /// File to read, - for standard input
+        file: PathBuf,
+    },
+    /// Print the otpauth:// URIs of all entries, one per line
+    Export {
+        /// Write to a file only readable by you instead
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List or restore previous generations of the database
+    Backups {
+        #[command(subcommand)]
+        action: BackupAction,
+    },
+    /// Change the password of the database
+    Rekey,
+    /// Encrypt the database to age or SSH public keys, or back to a password
+    #[command(group(ArgGroup::new("target").required(true).multiple(true).args(["recipient", "recipients_file", "passphrase"])))]
+    Encrypt {
+        /// age (age1...) or SSH (ssh-ed25519, ssh-rsa) public key
+        #[arg(short, long, conflicts_with = "passphrase")]
+        recipient: Vec<String>,
+        /// File with one public key per line, as taken by `age -R`
+        #[arg(short = 'R', long, conflicts_with = "passphrase")]
+        recipients_file: Vec<PathBuf>,
+        /// Protect the database with a password instead
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Manage who a team database is encrypted to
+    Team {
+        #[command(subcommand)]
+        action: TeamAction,
+    },
+    /// Register and pick named vaults
+    Vaults {
+        #[command(subcommand)]
+        action: VaultAction,
+    },
+}
+
+/// How `list`, `code` and `show` print entries.
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// json and tsv print the id, issuer, account, type, code, seconds
+    /// remaining and next code of entries for scripts
+    #[arg(long, value_enum, default_value_t)]
+    format: Format,
+    /// Also print the secret and otpauth:// URI of entries
+    #[arg(long)]
+    with_secrets: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VaultAction {
+    /// List the registered vaults, * marking the default one
+    List,
+    /// Register an existing database under a name
+    Add {
+        name: String,
+        path: PathBuf,
+        /// Make it the default vault
+        #[arg(long)]
+        default: bool,
+    },
+    /// Forget a vault, the database file is kept
+    Remove { name: String },
+    /// Make a vault the default one
+    Default { name: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TeamAction {
+    /// List the members with access
+    List,
+    /// Give a member access
+    Add {
+        /// Name to list the member under
+        name: String,
+        /// Their age (age1...) or SSH public key
+        recipient: String,
+    },
+    /// Revoke the access of a member
+    Remove {
+        /// Name the member is listed under
+        name: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupAction {
+    /// List the kept generations, 1 being the newest
+    List,
+    /// Replace the database with a kept generation
+    Restore {
+        /// Generation to restore, as shown by `backups list`
+        generation: usize,
+    },
+}
+
+/// Runs the command given on the command line.
+pub fn run(cli: Cli) -> Result<()> {
+    let mut ctx = Context::new(cli.db, cli.vault, cli.identity)?;
+    ctx.pass = PassSource::pick(
+        cli.passphrase_fd,
+        cli.passphrase_file,
+        cli.passphrase_cmd,
+        std::env::var_os(PASSPHRASE_ENV).is_some(),
+        ctx.config.security.passphrase_command.as_deref(),
+    );
+    let command = match cli.command {
+        Some(command) => command,
+        None if io::stdout().is_terminal() => return tui::run(ctx.open_all()?, &ctx.config.clock()),
+        None => {
+            Cli::command().print_help()?;
+            return Ok(());
+        }
+    };
+    match command {
+        Command::Init => {
+            onboarding()?;
+            Ok(())
+        }
+        Command::Add { uri, qr, manual, hex, icon } => add(uri, qr, manual, hex, icon, &ctx),
+        Command::List { output } => list(output, &ctx),
+        Command::Code { query, challenge, session, output } => code(&query, challenge, session, output, &ctx),
+        Command::Show { query, qr, icon, output } => show(&query, qr, icon, output, &ctx),
+        Command::Verify { query, code, window } => verify(&query, &code, window, &ctx),
+        Command::Remove { query, yes } => remove(&query, yes, &ctx),
+        Command::Rename { query, issuer, account } => {
+            edit(&query, Edit { issuer, account, ..Edit::default() }, &ctx)
+        }
+        Command::Edit { query, algorithm, digits, period, counter, tags, notes } => {
+            edit(&query, Edit { algo: algorithm, digits, period, counter, tags, notes, ..Edit::default() }, &ctx)
+        }
+        Command::Import { file } => import(&file, &ctx),
+        Command::Export { output } => export(output, &ctx),
+        Command::Backups { action } => backups(action, &ctx),
+        Command::Rekey => rekey(&ctx),
+        Command::Encrypt { recipient, recipients_file, passphrase } => {
+            encrypt(recipient, recipients_file, passphrase, &ctx)
+        }
+        Command::Team { action } => team(action, &ctx),
+        Command::Vaults { action } => vaults(action, &mut ctx),
+    }
+}
+
+/// What commands need to find and unlock a database: the configuration and
+/// the global flags overriding it.
+pub struct Context {
+    pub config: Config,
+    db: Option<PathBuf>,
+    vault: Option<String>,
+    identities: Vec<PathBuf>,
+    /// Where the password of passphrase protected databases comes from.
+    pub pass: PassSource,
+}
+
+impl Context {
+    pub fn new(db: Option<PathBuf>, vault: Option<String>, identities: Vec<PathBuf>) -> Result<Context> {
+        Ok(Context { config: Config::load()?, db, vault, identities, pass: PassSource::Prompt })
+    }
+
+    pub fn db_path(&self) -> Result<PathBuf> {
+        DB::get_db(self.db.as_deref(), self.vault.as_deref(), &self.config)
+    }
+
+    /// Identities given with `--identity`, the configured ones otherwise.
+    pub fn identities(&self, path: &Path) -> Vec<PathBuf> {
+        if self.identities.is_empty() {
+            self.config.identities_for(path)
+        } else {
+            self.identities.clone()
+        }
+    }
+
+    /// Works out how to unlock the database at `path`: identity files for one
+    /// encrypted to recipients, its password otherwise.
+    pub fn unlock(&self, path: &Path) -> Result<Key> {
+        if DB::needs_passphrase(path)? {
+            Ok(Key::Passphrase(self.passphrase(path)?))
+        } else {
+            Ok(Key::Identities(self.identities(path)))
+        }
+    }
+
+    /// The password of the database at `path`, from `pass`. Asking for it
+    /// without a terminal fails right away instead of hanging.
+    pub fn passphrase(&self, path: &Path) -> Result<Secret<String>> {
+        if self.pass == PassSource::Prompt && fs::File::open("/dev/tty").is_err() {
+            let err = format!(
+                "no terminal to ask for the password of {} in, pass it with --passphrase-fd, --passphrase-file, \
+                 --passphrase-cmd or {PASSPHRASE_ENV}",
+                path.display()
+            );
+            return Err(CliError::MissingInput { err }.into());
+        }
+        self.pass.read(&path.display().to_string())
+    }
+
+    /// Finds, unlocks and opens the database.
+    pub fn open(&self) -> Result<DB> {
+        self.open_path(self.db_path()?)
+    }
+
+    /// The databases the dashboard shows with a name for each: the one picked
+    /// with --db, --vault or ROTP_DB, otherwise the default one and every
+    /// registered vault, each unlocked on its own. Vaults that can't be opened
+    /// are left out with a warning, unless none can.
+    pub fn open_all(&self) -> Result<Vec<(String, DB)>> {
+        if self.db.is_some() || self.vault.is_some() || std::env::var_os("ROTP_DB").is_some() || self.config.vaults.is_empty() {
+            let db = self.open()?;
+            return Ok(vec![(db.path().display().to_string(), db)]);
+        }
+        let mut vaults: Vec<(String, PathBuf)> =
+            self.config.vaults.iter().map(|(name, vault)| (name.clone(), vault.path.clone())).collect();
+        if let Some(default) = self.config.resolve_db(None, None, None)? {
+            match vaults.iter().position(|(_, path)| *path == default) {
+                Some(index) => vaults[..=index].rotate_right(1),
+                None => vaults.insert(0, (default.display().to_string(), default)),
+            }
+        }
+        let mut opened = vec![];
+        let mut failed = None;
+        for (name, path) in vaults {
+            match self.open_path(path) {
+                Ok(db) => opened.push((name, db)),
+                Err(err) => {
+                    eprintln!("Leaving out vault {}: {}", name, err);
+                    failed = Some(err);
+                }
+            }
+        }
+        match failed {
+            Some(err) if opened.is_empty() => Err(err),
+            _ => Ok(opened),
+        }
+    }
+
+    fn open_path(&self, path: PathBuf) -> Result<DB> {
+        let mut db = DB::open(path.clone(), self.unlock(&path)?)?;
+        db.set_backups(self.config.security.backups);
+        if let Some(version) = db.upgraded_from() {
+            eprintln!(
+                "{} is in format {}, it will be upgraded to {} when saved",
+                path.display(),
+                version,
+                db.meta().format_version
+            );
+        }
+        Ok(db)
+    }
+}
+
+/// Index of the one entry matching `query`: its id, its exact "Issuer
+/// (account)" label, or the best fuzzy match over issuer, account, tags and
+/// notes when no other entry matches as well.
+pub fn select(entries: &[OTP], query: &str) -> Result<usize> {
+    if let Some(index) = entries.iter().position(|otp| otp.id() == query) {
+        return Ok(index);
+    }
+    let exact: Vec<usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, otp)| otp.to_string().to_lowercase() == query.to_lowercase())
+        .map(|(index, _)| index)
+        .collect();
+    if let [index] = exact.as_slice() {
+        return Ok(*index);
+    }
+    let matches = search(entries, query);
+    match matches.as_slice() {
+        [] => Err(CliError::NoMatch { query: query.to_string() }.into()),
+        [(index, _)] => Ok(*index),
+        [(index, best), (_, next), ..] if best > next => Ok(*index),
+        [(_, best), ..] => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .take_while(|(_, score)| score == best)
+                .map(|(index, _)| entries[*index].to_string())
+                .collect();
+            Err(CliError::Ambiguous { query: query.to_string(), candidates: candidates.join(", ") }.into())
+        }
+    }
+}
+
+/// Parses an otpauth:// URI given by the user, telling them about anything
+/// that looks wrong in it.
+fn parse_uri(uri: &str) -> Result<OTP> {
+    let otp = OTP::parse_uri(uri)?;
+    if let Some(warning) = OTP::uri_warning(uri) {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(otp)
+}
+
+pub fn add(
+    uri: Option<String>,
+    qr: Option<PathBuf>,
+    manual: bool,
+    hex: bool,
+    icon: Option<PathBuf>,
+    ctx: &Context,
+) -> Result<()> {
+    let mut qr_image = None;
+    let otp = if let Some(uri) = uri {
+        parse_uri(&uri)?
+    } else if let Some(qr) = qr {
+        let data = fs::read(&qr)?;
+        let uri = decode_qr(&mut image::load_from_memory(&data)?)?;
+        qr_image = Some(data);
+        parse_uri(&uri)?
+    } else if manual {
+        let issuer = Text::new("Issuer:").prompt()?;
+        let account = Text::new("Account:").prompt()?;
+        let secret = rpassword::prompt_password("Secret: ")?;
+        let encoding = if hex { SecretEncoding::Hex } else { SecretEncoding::Base32 };
+        OTP::from_manual_entry(&issuer, &account, &secret, encoding)?
+    } else {
+        return Err(CliError::MissingInput { err: "give a URI, --qr or --manual".to_string() }.into());
+    };
+
+    let mut db = ctx.open()?;
+    if db.entries().iter().any(|other| other.to_uri() == otp.to_uri()) {
+        println!("{} is already in the database", otp);
+        return Ok(());
+    }
+    let id = otp.id().to_string();
+    let label = otp.to_string();
+    db.add(otp);
+    if let Some(data) = qr_image {
+        db.set_image(&id, ImageKind::Qr, data)?;
+    }
+    if let Some(icon) = icon {
+        db.set_image(&id, ImageKind::Icon, fs::read(icon)?)?;
+    }
+    save(&mut db)?;
+    println!("Added {}", label);
+    Ok(())
+}
+
+pub fn list(output: OutputArgs, ctx: &Context) -> Result<()> {
+    let db = ctx.open()?;
+    let clock = ctx.config.clock();
+    let records = || {
+        db.entries()
+            .iter()
+            .map(|otp| Record::new(otp, current_code(otp, &clock)?, &clock, output.with_secrets))
+            .collect::<Result<Vec<Record>>>()
+    };
+    match output.format {
+        Format::Text if db.entries().is_empty() => println!("No entries yet, add one with `rotp add`"),
+        Format::Text => {
+            for otp in db.entries() {
+                match output.with_secrets {
+                    true => println!("{}\t{}\t{}\t{}", otp.id(), otp.kind(), otp, otp.to_uri()),
+                    false => println!("{}\t{}\t{}", otp.id(), otp.kind(), otp),
+                }
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string(&records()?)?),
+        Format::Tsv => {
+            for record in records()? {
+                println!("{}", record.tsv());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the code of an entry. A counter based entry is moved past the code
+/// and saved first, with the code made from the counter on disk, so the code
+/// is never handed out twice.
+pub fn code(query: &str, challenge: Option<String>, session: Option<String>, output: OutputArgs, ctx: &Context) -> Result<()> {
+    let mut db = ctx.open()?;
+    let index = select(db.entries(), query)?;
+    let clock = ctx.config.clock();
+    let otp = &db.entries()[index];
+    let id = otp.id().to_string();
+    let session = session.map(|session| data_encoding::HEXLOWER_PERMISSIVE.decode(session.as_bytes())).transpose()?;
+    let answer = |otp: &OTP, challenge: &str, pin: Option<&Secret<String>>| {
+        let pin = pin.map(|pin| pin.expose_secret().as_str());
+        otp.answer(challenge, pin, session.as_deref(), &clock).expect("OCRA entries answer challenges")
+    };
+    let pin = match (otp.kind(), &challenge) {
+        ("OCRA", Some(challenge)) => match answer(otp, challenge, None) {
+            Err(ocra::Error::MissingInput("password")) => {
+                if fs::File::open("/dev/tty").is_err() {
+                    let err = format!("{} needs a PIN and there is no terminal to ask for it in", otp);
+                    return Err(CliError::MissingInput { err }.into());
+                }
+                Some(Secret::new(rpassword::prompt_password(format!("PIN of {}: ", otp))?))
+            }
+            _ => None,
+        },
+        ("OCRA", None) => {
+            return Err(CliError::MissingInput { err: format!("{} answers challenges, pass --challenge", otp) }.into())
+        }
+        _ => None,
+    };
+    let make = |otp: &OTP| -> Result<String> {
+        match &challenge {
+            Some(challenge) if otp.kind() == "OCRA" => Ok(answer(otp, challenge, pin.as_ref())?),
+            _ => Ok(otp.code(&clock)?),
+        }
+    };
+    let code = match otp.counter() {
+        Some(_) => db.use_counter(&id, make)?,
+        None => make(otp)?,
+    };
+    let otp = db.find(&id).expect("the entry the code was made for is kept");
+    match output.format {
+        Format::Text => println!("{}", code),
+        Format::Json => println!("{}", serde_json::to_string(&Record::new(otp, Some(code), &clock, output.with_secrets)?)?),
+        Format::Tsv => println!("{}", Record::new(otp, Some(code), &clock, output.with_secrets)?.tsv()),
+    }
+    Ok(())
+}
+
+/// Prints an entry without handing out the code of counter based ones, and
+/// writes out the images kept with it when asked to.
+pub fn show(query: &str, qr: Option<PathBuf>, icon: Option<PathBuf>, output: OutputArgs, ctx: &Context) -> Result<()> {
+    let db = ctx.open()?;
+    let clock = ctx.config.clock();
+    let otp = &db.entries()[select(db.entries(), query)?];
+    for (kind, what, file) in [(ImageKind::Qr, "QR code", qr), (ImageKind::Icon, "icon", icon)] {
+        let Some(file) = file else { continue };
+        let Some(data) = db.image(otp.id(), kind) else {
+            return Err(CliError::MissingInput { err: format!("no {} is kept with {}", what, otp) }.into());
+        };
+        // temporary files are created readable by the owner only
+        write_atomic(&file, data)?;
+        eprintln!("Wrote the {} of {} to {}", what, otp, file.display());
+    }
+    let record = Record::new(otp, current_code(otp, &clock)?, &clock, output.with_secrets)?;
+    match output.format {
+        Format::Text => {
+            println!("{}", record.text());
+            if !otp.tags().is_empty() {
+                println!("tags: {}", otp.tags().join(", "));
+            }
+            if !otp.notes().is_empty() {
+                println!("notes: {}", otp.notes());
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string(&record)?),
+        Format::Tsv => println!("{}", record.tsv()),
+    }
+    Ok(())
+}
+
+/// Checks a code, moving an HOTP entry past the counter it was made with
+/// like a server would.
+pub fn verify(query: &str, code: &str, window: u64, ctx: &Context) -> Result<()> {
+    let mut db = ctx.open()?;
+    let index = select(db.entries(), query)?;
+    let otp = &mut db.entries_mut()[index];
+    let label = otp.to_string();
+    let verified = match otp.verify(code, window, &ctx.config.clock()) {
+        Some(verified) => verified?,
+        None => {
+            let err = format!("{} codes of {} can't be verified", otp.kind(), label);
+            return Err(CliError::MissingInput { err }.into());
+        }
+    };
+    if !verified {
+        return Err(CliError::Rejected { code: code.to_string(), label }.into());
+    }
+    if otp.period().is_none() {
+        save(&mut db)?;
+    }
+    println!("{} is a valid code of {}", code, label);
+    Ok(())
+}
+
+pub fn remove(query: &str, yes: bool, ctx: &Context) -> Result<()> {
+    let mut db = ctx.open()?;
+    let index = select(db.entries(), query)?;
+    let label = db.entries()[index].to_string();
+    if !yes && !Confirm::new(&format!("Remove {}?", label)).with_default(false).prompt()? {
+        return Err(CliError::Cancelled.into());
+    }
+    db.remove(index);
+    save(&mut db)?;
+    println!("Removed {}", label);
+    Ok(())
+}
+
+pub fn edit(query: &str, edit: Edit, ctx: &Context) -> Result<()> {
+    let mut db = ctx.open()?;
+    let index = select(db.entries(), query)?;
+    let otp = &mut db.entries_mut()[index];
+    otp.edit(edit)?;
+    let label = otp.to_string();
+    save(&mut db)?;
+    println!("Updated {}", label);
+    Ok(())
+}
+
+pub fn import(file: &Path, ctx: &Context) -> Result<()> {
+    let mut raw = String::new();
+    if file == Path::new("-") {
+        io::stdin().read_to_string(&mut raw)?;
+    } else {
+        raw = fs::read_to_string(file)?;
+    }
+    let mut otps = vec![];
+    for (number, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let otp = OTP::parse_uri(line).map_err(|e| anyhow::anyhow!("line {}: {}", number + 1, e))?;
+        if let Some(warning) = OTP::uri_warning(line) {
+            eprintln!("Warning: line {}: {}", number + 1, warning);
+        }
+        otps.push(otp);
+    }
+
+    let mut db = ctx.open()?;
+    let (mut added, mut skipped) = (0, 0);
+    for otp in otps {
+        if db.entries().iter().any(|other| other.to_uri() == otp.to_uri()) {
+            skipped += 1;
+        } else {
+            db.add(otp);
+            added += 1;
+        }
+    }
+    save(&mut db)?;
+    println!("Imported {} entries, skipped {} already in the database", added, skipped);
+    Ok(())
+}
+
+pub fn export(output: Option<PathBuf>, ctx: &Context) -> Result<()> {
+    let db = ctx.open()?;
+    let mut uris = String::new();
+    for otp in db.entries() {
+        uris.push_str(&otp.to_uri());
+        uris.push('\n');
+    }
+    match output {
+        // temporary files are created readable by the owner only
+        Some(output) => {
+            write_atomic(&output, uris.as_bytes())?;
+            eprintln!("Exported {} entries to {}, it holds the secrets in clear", db.entries().len(), output.display());
+        }
+        None => print!("{}", uris),
+    }
+    Ok(())
+}
+
+pub fn backups(action: BackupAction, ctx: &Context) -> Result<()> {
+    let path = ctx.db_path()?;
+    match action {
+        BackupAction::List => {
+            let backups = DB::list_backups(&path)?;
+            if backups.is_empty() {
+                println!("No backups of {}", path.display());
+            }
+            for backup in backups {
+                let age = backup.modified.elapsed().unwrap_or_default().as_secs();
+                println!(
+                    "{}\t{}\t{} minutes ago",
+                    backup.generation,
+                    backup.path.display(),
+                    age / 60
+                );
+            }
+        }
+        BackupAction::Restore { generation } => {
+            let key = ctx.unlock(&path)?;
+            DB::restore_backup(&path, generation, key, ctx.config.security.backups)?;
+            println!(
+                "Restored backup {} into {}, the replaced database is now backup 1",
+                generation,
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn rekey(ctx: &Context) -> Result<()> {
+    let path = ctx.db_path()?;
+    let old = ctx.passphrase(&path)?;
+    println!("Choose the new password");
+    let new = prompt_new_pass()?;
+    DB::rekey(&path, old, new, ctx.config.security.backups)?;
+    println!(
+        "Changed the password of {}, backups still open with the old one",
+        path.display()
+    );
+    Ok(())
+}
+
+pub fn encrypt(
+    recipients: Vec<String>,
+    recipients_files: Vec<PathBuf>,
+    passphrase: bool,
+    ctx: &Context,
+) -> Result<()> {
+    let mut db = ctx.open()?;
+    let path = db.path().to_path_buf();
+    let identities = ctx.identities(&path);
+    let done = if passphrase {
+        println!("Choose the new password");
+        db.encrypt_to_passphrase(prompt_new_pass()?);
+        format!("{} is now protected by a password", path.display())
+    } else {
+        if identities.is_empty() {
+            let err = "pass the identity you will unlock the database with through --identity or the configuration";
+            return Err(CliError::MissingInput { err: err.to_string() }.into());
+        }
+        let mut recipients = recipients;
+        for file in recipients_files {
+            recipients.extend(parse_recipients(&fs::read_to_string(file)?));
+        }
+        let count = recipients.len();
+        db.encrypt_to_recipients(recipients, identities)?;
+        format!("{} is now encrypted to {} recipients", path.display(), count)
+    };
+    save(&mut db)?;
+    println!("{}", done);
+    Ok(())
+}
+
+pub fn team(action: TeamAction, ctx: &Context) -> Result<()> {
+    let mut db = ctx.open()?;
+    let path = db.path().to_path_buf();
+    let identities = ctx.identities(&path);
+    match action {
+        TeamAction::List => {
+            let team = db.team()?;
+            if team.is_empty() {
+                println!("{} is protected by a password, not shared with a team", path.display());
+            }
+            for member in team {
+                println!("{}\t{}", member.name, member.recipient);
+            }
+            return Ok(());
+        }
+        TeamAction::Add { name, recipient } => {
+            db.add_member(TeamMember { name: name.clone(), recipient }, identities)?;
+            save(&mut db)?;
+            println!("{} now has access to {}", name, path.display());
+        }
+        TeamAction::Remove { name } => {
+            db.remove_member(&name, identities)?;
+            save(&mut db)?;
+            println!(
+                "{} no longer has access to {}, rotate the secrets they could see since older copies still open with their key",
+                name,
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub fn vaults(action: VaultAction, ctx: &mut Context) -> Result<()> {
+    let config = &mut ctx.config;
+    match action {
+        VaultAction::List => {
+            if config.vaults.is_empty() {
+                println!("No vaults registered, add one with `rotp vaults add <name> <path>`");
+            }
+            for (name, vault) in &config.vaults {
+                let marker = if config.default.as_deref() == Some(name.as_str()) { "*" } else { " " };
+                let missing = if vault.path.exists() { "" } else { "\t(missing)" };
+                println!("{} {}\t{}{}", marker, name, vault.path.display(), missing);
+            }
+            return Ok(());
+        }
+        VaultAction::Add { name, path, default } => {
+            if !path.exists() {
+                println!("Warning: {} does not exist yet", path.display());
+            }
+            let path = path.canonicalize().unwrap_or(path);
+            config.add_vault(&name, VaultConfig { path, identities: vec![] })?;
+            if default {
+                config.set_default(&name)?;
+            }
+            println!("Registered vault {}", name);
+        }
+        VaultAction::Remove { name } => {
+            let vault = config.remove_vault(&name)?;
+            println!("Forgot vault {}, {} was left in place", name, vault.path.display());
+        }
+        VaultAction::Default { name } => {
+            config.set_default(&name)?;
+            println!("{} is now the default vault", name);
+        }
+    }
+    config.save()
+}
+
+/// Saves `db`, offering to reload and merge when another ROTP process saved
+/// the same database in the meantime.
+pub fn save(db: &mut DB) -> Result<()> {
+    loop {
+        let err = match db.save() {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        if !matches!(err.downcast_ref::<StorageError>(), Some(StorageError::ChangedOnDisk { .. })) {
+            return Err(err);
+        }
+        let merge = Confirm::new(&format!("{err}. Reload it and merge your changes?"))
+            .with_default(true)
+            .prompt()?;
+        if !merge {
+            return Err(err);
+        }
+        db.reload_and_merge()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{select, CliError};
+    use crate::types::OTP;
+
+    #[test]
+    fn selecting() {
+        let entries: Vec<OTP> = ["ACME:john", "ACME:johnny", "Globex:jane", "GitHub:jane", "GitLab:jane"]
+            .iter()
+            .map(|label| OTP::parse_uri(&format!("otpauth://totp/{label}?secret=JBSWY3DPEHPK3PXP")).unwrap())
+            .collect();
+        assert_eq!(select(&entries, "globex").unwrap(), 2);
+        assert_eq!(select(&entries, "acme (john)").unwrap(), 0);
+        assert_eq!(select(&entries, entries[1].id()).unwrap(), 1);
+        let err = select(&entries, "john").unwrap_err();
+        assert!(matches!(err.downcast_ref::<CliError>(), Some(CliError::Ambiguous { .. })));
+        assert_eq!(select(&entries, "gith").unwrap(), 3);
+        let err = select(&entries, "git jane").unwrap_err();
+        assert!(matches!(err.downcast_ref::<CliError>(), Some(CliError::Ambiguous { .. })));
+        let err = select(&entries, "initech").unwrap_err();
+        assert!(matches!(err.downcast_ref::<CliError>(), Some(CliError::NoMatch { .. })));
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::{SystemTime, SystemTimeError};
+
+/// Source of the current time for TOTP generation and countdowns, so time can
+/// be fixed in tests or corrected for drift.
+pub trait Clock {
+    /// Seconds since the Unix epoch. Note that times before Unix epoch are
+    /// not supported.
+    fn now(&self) -> Result<u64, SystemTimeError>;
+}
+
+/// The system's wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Result<u64, SystemTimeError> {
+        Ok(SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs())
+    }
+}
+
+/// A clock that is stuck at a given Unix time.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Result<u64, SystemTimeError> {
+        Ok(self.0)
+    }
+}
+
+/// Shifts another clock by a number of seconds, used to correct drift.
+#[derive(Debug, Clone, Copy)]
+pub struct OffsetClock<C: Clock> {
+    inner: C,
+    offset: i64,
+}
+
+impl<C: Clock> OffsetClock<C> {
+    pub fn new(inner: C, offset: i64) -> OffsetClock<C> {
+        OffsetClock { inner, offset }
+    }
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> Result<u64, SystemTimeError> {
+        Ok(self.inner.now()?.saturating_add_signed(self.offset))
+    }
+}
+
+/// Seconds left before the code of a TOTP with the given period changes.
+pub fn seconds_remaining(clock: &dyn Clock, period: u64) -> Result<u64, SystemTimeError> {
+    Ok(period - clock.now()? % period)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seconds_remaining, Clock, FixedClock, OffsetClock};
+
+    #[test]
+    fn offset() {
+        assert_eq!(OffsetClock::new(FixedClock(100), 5).now().unwrap(), 105);
+        assert_eq!(OffsetClock::new(FixedClock(100), -5).now().unwrap(), 95);
+        assert_eq!(OffsetClock::new(FixedClock(3), -5).now().unwrap(), 0);
+    }
+
+    #[test]
+    fn countdown() {
+        assert_eq!(seconds_remaining(&FixedClock(0), 30).unwrap(), 30);
+        assert_eq!(seconds_remaining(&FixedClock(29), 30).unwrap(), 1);
+        assert_eq!(seconds_remaining(&FixedClock(30), 30).unwrap(), 30);
+        assert_eq!(
+            seconds_remaining(&OffsetClock::new(FixedClock(30), -1), 30).unwrap(),
+            1
+        );
+    }
+}
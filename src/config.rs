@@ -0,0 +1,271 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{OffsetClock, SystemClock};
+use crate::storage::{write_atomic, DEFAULT_BACKUPS};
+
+/// Settings read from `$XDG_CONFIG_HOME/rotp/config.toml`, falling back to
+/// `~/.config/rotp/config.toml`. Every key is optional:
+///
+/// ```toml
+/// default = "work"            # a vault name or a path
+///
+/// [vaults.work]
+/// path = "/home/me/work.tar.rotp"
+/// identities = ["/home/me/.ssh/id_ed25519"]
+///
+/// [ui]
+/// clock_offset = -2           # seconds added to the system clock
+///
+/// [security]
+/// backups = 5
+/// identities = ["/home/me/.config/age/key.txt"]
+/// passphrase_command = "pass show rotp"
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Vault used when none is picked with `--db`, `--vault` or `ROTP_DB`,
+    /// either the name of an entry in `vaults` or a path.
+    pub default: Option<String>,
+    pub vaults: BTreeMap<String, VaultConfig>,
+    pub ui: UiConfig,
+    pub security: SecurityConfig,
+}
+
+/// A named vault.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultConfig {
+    pub path: PathBuf,
+    /// Identities tried before `security.identities` for this vault.
+    #[serde(default)]
+    pub identities: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Seconds added to the system clock when computing TOTP codes, to make up
+    /// for a clock that drifted.
+    pub clock_offset: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// Previous generations of a vault kept on save.
+    pub backups: usize,
+    /// Identities used to unlock vaults encrypted to recipients when none are
+    /// given with `--identity`.
+    pub identities: Vec<PathBuf>,
+    /// Shell command printing the password of passphrase protected vaults,
+    /// used when none is given on the command line or in `ROTP_PASSPHRASE`.
+    pub passphrase_command: Option<String>,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> SecurityConfig {
+        SecurityConfig { backups: DEFAULT_BACKUPS, identities: vec![], passphrase_command: None }
+    }
+}
+
+impl Config {
+    /// Location of the configuration file.
+    pub fn path() -> Result<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var("HOME").context("neither XDG_CONFIG_HOME nor HOME is set")?).join(".config"),
+        };
+        Ok(base.join("rotp").join("config.toml"))
+    }
+
+    /// Reads the configuration file, defaults if there is none.
+    pub fn load() -> Result<Config> {
+        Config::load_from(&Config::path()?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(raw) => toml::from_str(&raw).with_context(|| format!("invalid configuration in {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes the configuration file, creating its directory if needed.
+    pub fn save(&self) -> Result<()> {
+        self.save_to(&Config::path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(path, toml::to_string(self)?.as_bytes())
+    }
+
+    /// Picks the vault to use: the `--db` flag, then the vault named with
+    /// `--vault`, then the `ROTP_DB` variable, then `default` from this file.
+    pub fn resolve_db(&self, flag: Option<&Path>, vault: Option<&str>, env: Option<PathBuf>) -> Result<Option<PathBuf>> {
+        if let Some(path) = flag {
+            return Ok(Some(path.to_path_buf()));
+        }
+        if let Some(name) = vault {
+            return Ok(Some(self.vault(name)?.path.clone()));
+        }
+        if let Some(path) = env {
+            return Ok(Some(path));
+        }
+        let default = match &self.default {
+            Some(default) => default,
+            None => return Ok(None),
+        };
+        match self.vaults.get(default) {
+            Some(vault) => Ok(Some(vault.path.clone())),
+            None => Ok(Some(PathBuf::from(default))),
+        }
+    }
+
+    pub fn vault(&self, name: &str) -> Result<&VaultConfig> {
+        match self.vaults.get(name) {
+            Some(vault) => Ok(vault),
+            None => bail!("no vault named {name}, see `rotp vaults list`"),
+        }
+    }
+
+    /// Registers a vault under `name`.
+    pub fn add_vault(&mut self, name: &str, vault: VaultConfig) -> Result<()> {
+        if self.vaults.contains_key(name) {
+            bail!("a vault named {name} already exists");
+        }
+        self.vaults.insert(name.to_string(), vault);
+        Ok(())
+    }
+
+    /// Forgets the vault registered under `name`, the file itself is kept.
+    pub fn remove_vault(&mut self, name: &str) -> Result<VaultConfig> {
+        let vault = self.vault(name)?.clone();
+        self.vaults.remove(name);
+        if self.default.as_deref() == Some(name) {
+            self.default = None;
+        }
+        Ok(vault)
+    }
+
+    /// Makes the vault registered under `name` the default one.
+    pub fn set_default(&mut self, name: &str) -> Result<()> {
+        self.vault(name)?;
+        self.default = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Identities to unlock the vault at `path` with when none were given on
+    /// the command line.
+    pub fn identities_for(&self, path: &Path) -> Vec<PathBuf> {
+        self.vaults
+            .values()
+            .filter(|vault| vault.path == path)
+            .flat_map(|vault| vault.identities.iter().cloned())
+            .chain(self.security.identities.iter().cloned())
+            .collect()
+    }
+
+    /// The clock TOTP codes are computed with.
+    pub fn clock(&self) -> OffsetClock<SystemClock> {
+        OffsetClock::new(SystemClock, self.ui.clock_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tempfile::tempdir;
+
+    use super::{Config, VaultConfig};
+
+    const SAMPLE: &str = r#"
+default = "work"
+
+[vaults.work]
+path = "/vaults/work.tar.rotp"
+identities = ["/keys/work"]
+
+[vaults.personal]
+path = "/vaults/personal.tar.rotp"
+
+[security]
+identities = ["/keys/me"]
+"#;
+
+    #[test]
+    fn defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.security.backups, 5);
+        assert_eq!(config.ui.clock_offset, 0);
+        assert!(config.resolve_db(None, None, None).unwrap().is_none());
+        let dir = tempdir().unwrap();
+        assert!(Config::load_from(&dir.path().join("missing.toml")).unwrap().vaults.is_empty());
+    }
+
+    #[test]
+    fn precedence() {
+        let config: Config = toml::from_str(SAMPLE).unwrap();
+        let flag = Path::new("/flag.tar.rotp");
+        let env = PathBuf::from("/env.tar.rotp");
+        let resolve = |flag, vault, env| config.resolve_db(flag, vault, env).unwrap().unwrap();
+        assert_eq!(resolve(Some(flag), Some("personal"), Some(env.clone())), flag);
+        assert_eq!(resolve(None, Some("personal"), Some(env.clone())), Path::new("/vaults/personal.tar.rotp"));
+        assert_eq!(resolve(None, None, Some(env.clone())), env);
+        assert_eq!(resolve(None, None, None), Path::new("/vaults/work.tar.rotp"));
+        assert!(config.resolve_db(None, Some("nope"), None).is_err());
+
+        let config: Config = toml::from_str("default = \"/some/path.tar.rotp\"").unwrap();
+        assert_eq!(config.resolve_db(None, None, None).unwrap().unwrap(), Path::new("/some/path.tar.rotp"));
+    }
+
+    #[test]
+    fn identities() {
+        let config: Config = toml::from_str(SAMPLE).unwrap();
+        assert_eq!(
+            config.identities_for(Path::new("/vaults/work.tar.rotp")),
+            vec![PathBuf::from("/keys/work"), PathBuf::from("/keys/me")]
+        );
+        assert_eq!(config.identities_for(Path::new("/vaults/personal.tar.rotp")), vec![PathBuf::from("/keys/me")]);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("rotp").join("config.toml");
+        let mut config = Config::load_from(&path).unwrap();
+        config.default = Some("/vaults/new.tar.rotp".to_string());
+        config.security.backups = 2;
+        config.save_to(&path).unwrap();
+        let config = Config::load_from(&path).unwrap();
+        assert_eq!(config.default.as_deref(), Some("/vaults/new.tar.rotp"));
+        assert_eq!(config.security.backups, 2);
+    }
+
+    #[test]
+    fn vaults() {
+        let mut config: Config = toml::from_str(SAMPLE).unwrap();
+        let vault = VaultConfig { path: PathBuf::from("/vaults/break-glass.tar.rotp"), identities: vec![] };
+        assert!(config.add_vault("work", vault.clone()).is_err());
+        config.add_vault("break-glass", vault).unwrap();
+        assert!(config.set_default("nope").is_err());
+        config.set_default("break-glass").unwrap();
+        assert_eq!(config.resolve_db(None, None, None).unwrap().unwrap(), Path::new("/vaults/break-glass.tar.rotp"));
+
+        config.remove_vault("break-glass").unwrap();
+        assert!(config.default.is_none());
+        assert!(config.remove_vault("break-glass").is_err());
+        assert_eq!(config.vaults.keys().collect::<Vec<_>>(), vec!["personal", "work"]);
+    }
+}
@@ -1,29 +1,26 @@
-use image::io::Reader;
-use storage::onboarding;
+use std::process::ExitCode;
+
+use clap::Parser;
+mod cli;
+mod clock;
+mod config;
+mod migrate;
+mod ocra;
 mod otp;
+mod output;
 mod parse;
+mod search;
 mod err;
 mod storage;
-use storage::{get_pass, DB};
+mod tui;
 mod types;
-use types::*;
 
-
-fn main() -> Result<(), anyhow::Error> {
-    let mut otp_list:Vec<OTP> = vec![];
-    let mut lol = Reader::open("/home/adi/code/ROTP/testing/canvas.png")?
-    .with_guessed_format()?
-    .decode()?;
-let otp = OTP::parse_uri(&"otpauth://totp/grwrwghwRGHRGWRGW?secret=BASE32SECRET3232&issuer=grwrwghwRGHRGWRGW&algorithm=SHA1&digits=6&period=30".to_string()).unwrap();
-println!("{:?}", otp);
-    let uri = decode_qr(&mut lol)?;
-    println!("{}", uri);
-    let db =  DB::new(get_pass(&DB::get_name()?)?);
-    Ok(())
+fn main() -> ExitCode {
+    match cli::run(cli::Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:#}");
+            ExitCode::from(cli::exit_code(&err))
+        }
+    }
 }
-
-
-
-
-
-
@@ -0,0 +1,169 @@
+use std::{path::PathBuf, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+use snafu::prelude::*;
+
+use crate::types::new_id;
+
+/// Version of the archive layout written by this build. Bump it together with
+/// a new step in `upgrade`. Storage tests upgrade
+/// `testing/vaults/v0.tar.rotp`, written before versioning with passphrase
+/// `rotp` and the ACME:john TOTP entry.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Name of the archive member describing the archive itself. Archives
+/// without it predate versioning and are version 0.
+pub const META_FILE: &str = "meta.toml";
+
+#[derive(Debug, Snafu)]
+pub enum MigrationError {
+    #[snafu(display("Database format {version} was written by a newer ROTP, this one reads up to {FORMAT_VERSION}"))]
+    TooNew { version: u32 },
+    #[snafu(display("Database format {version} is invalid: {err}"))]
+    Broken { version: u32, err: String },
+}
+
+/// Contents of `meta.toml`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    pub format_version: u32,
+    /// Unix time the database was created at, 0 if unknown.
+    pub created: u64,
+    /// ROTP version that last wrote the database.
+    pub writer: String,
+}
+
+impl Meta {
+    /// Metadata of a database created now.
+    pub fn new() -> Meta {
+        let created = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        Meta { format_version: FORMAT_VERSION, created, writer: writer() }
+    }
+
+    /// What an archive without `meta.toml` is taken for.
+    pub fn unversioned() -> Meta {
+        Meta { format_version: 0, created: 0, writer: "unknown".to_string() }
+    }
+
+    /// The metadata to write when saving with this build.
+    pub fn rewritten(&self) -> Meta {
+        Meta { format_version: FORMAT_VERSION, created: self.created, writer: writer() }
+    }
+}
+
+fn writer() -> String {
+    format!("rotp {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// An archive as read from the tar: secrets.toml before parsing and every
+/// other member but `meta.toml`.
+pub struct RawArchive {
+    pub secrets: String,
+    pub members: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// Brings an archive written in format `version` up to `FORMAT_VERSION`, one
+/// version at a time. Archives from newer builds are refused rather than
+/// risking to drop what they added.
+pub fn upgrade(version: u32, archive: &mut RawArchive) -> Result<(), MigrationError> {
+    if version > FORMAT_VERSION {
+        return Err(MigrationError::TooNew { version });
+    }
+    for from in version..FORMAT_VERSION {
+        match from {
+            0 => v0_to_v1(archive)?,
+            _ => unreachable!("no migration from format {from}"),
+        }
+    }
+    Ok(())
+}
+
+/// Version 1 adds `meta.toml`, which is written on the next save, and gives
+/// every entry an `id`, replacing the unused `img_path` placeholder, so images
+/// can be stored under `images/<id>/`. Entries may also carry `tags` and
+/// `notes` from then on.
+fn v0_to_v1(archive: &mut RawArchive) -> Result<(), MigrationError> {
+    let broken = |err: String| MigrationError::Broken { version: 0, err };
+    let mut table: toml::Table = toml::from_str(&archive.secrets).map_err(|err| broken(err.to_string()))?;
+    let secrets = table
+        .get_mut("secrets")
+        .ok_or_else(|| broken("no [secrets] table".to_string()))?;
+    let entries = secrets
+        .as_table_mut()
+        .and_then(|secrets| secrets.get_mut("entries"))
+        .and_then(|entries| entries.as_array_mut());
+    for entry in entries.into_iter().flatten() {
+        let entry = entry.as_table_mut().ok_or_else(|| broken("entry is not a table".to_string()))?;
+        entry.remove("img_path");
+        entry.insert("id".to_string(), toml::Value::String(new_id()));
+    }
+    archive.secrets = toml::to_string(&table).map_err(|err| broken(err.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upgrade, Meta, MigrationError, RawArchive, FORMAT_VERSION};
+    use crate::parse;
+
+    #[test]
+    fn versions() {
+        let mut archive = RawArchive { secrets: "[secrets]".to_string(), members: vec![] };
+        upgrade(0, &mut archive).unwrap();
+        upgrade(FORMAT_VERSION, &mut archive).unwrap();
+        assert!(matches!(upgrade(FORMAT_VERSION + 1, &mut archive), Err(MigrationError::TooNew { .. })));
+
+        let mut broken = RawArchive { secrets: String::new(), members: vec![] };
+        assert!(upgrade(0, &mut broken).is_err());
+    }
+
+    #[test]
+    fn meta() {
+        let meta = Meta::unversioned().rewritten();
+        assert_eq!(meta.format_version, FORMAT_VERSION);
+        assert_eq!(meta.created, 0);
+        assert!(meta.writer.starts_with("rotp "));
+        assert!(Meta::new().created > 0);
+    }
+
+    #[test]
+    fn ids() {
+        let v0 = r#"
+[[secrets.entries]]
+type = "TOTP"
+secret = "JBSWY3DPEHPK3PXP"
+issuer = "ACME"
+account = "john"
+algo = "SHA1"
+digits = 6
+step = 30
+full_uri = "otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME"
+img_path = "/wip"
+
+[[secrets.entries]]
+type = "HOTP"
+secret = "JBSWY3DPEHPK3PXP"
+issuer = "ACME"
+account = "jane"
+algo = "SHA1"
+digits = 6
+counter = 1
+full_uri = "otpauth://hotp/ACME:jane?secret=JBSWY3DPEHPK3PXP&issuer=ACME&counter=1"
+img_path = "/wip"
+"#;
+        let mut archive = RawArchive { secrets: v0.to_string(), members: vec![] };
+        upgrade(0, &mut archive).unwrap();
+        assert!(!archive.secrets.contains("img_path"));
+        let entries = parse::deserialize(&archive.secrets).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id().len(), 16);
+        assert_ne!(entries[0].id(), entries[1].id());
+
+        let mut empty = RawArchive { secrets: "[secrets]".to_string(), members: vec![] };
+        upgrade(0, &mut empty).unwrap();
+        assert!(parse::deserialize(&empty.secrets).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+//! OCRA: OATH Challenge-Response Algorithm ([RFC 6287](https://www.rfc-editor.org/rfc/rfc6287))
+
+use err_derive::Error;
+use ring::{digest, hmac};
+
+use crate::clock::Clock;
+use crate::otp::{self, decode_secret, encode_digest, hmac_algorithm};
+use crate::types::Algo;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(display = "invalid OCRA suite: {}", _0)]
+    InvalidSuite(String),
+    #[error(display = "invalid OCRA challenge: {}", _0)]
+    InvalidQuestion(String),
+    #[error(display = "invalid OCRA session information: {}", _0)]
+    InvalidSession(String),
+    #[error(display = "OCRA suite requires a {} input", _0)]
+    MissingInput(&'static str),
+    #[error(display = "{}", _0)]
+    Otp(#[error(source)] otp::Error),
+}
+
+/// Size in bytes the challenge is padded to in the OCRA data input.
+const QUESTION_LENGTH: usize = 128;
+
+/// Format of the challenge question of a suite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuestionFormat {
+    Numeric,
+    Alphanumeric,
+    Hex,
+}
+
+/// A parsed OCRA suite, e.g. `OCRA-1:HOTP-SHA1-6:QN08`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suite {
+    raw: String,
+    pub algo: Algo,
+    pub digits: u32,
+    pub counter: bool,
+    pub question_format: QuestionFormat,
+    pub question_length: usize,
+    pub password: Option<Algo>,
+    pub session_length: Option<usize>,
+    /// Length of a time step in seconds, when the suite uses timestamps.
+    pub timestamp_step: Option<u64>,
+}
+
+/// The values a suite may ask for when answering a challenge.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Inputs<'a> {
+    pub counter: Option<u64>,
+    pub question: &'a str,
+    /// The PIN/password in clear, it is hashed with the suite's algorithm.
+    pub password: Option<&'a str>,
+    pub session: Option<&'a [u8]>,
+}
+
+impl Suite {
+    pub fn parse(raw: &str) -> Result<Suite, Error> {
+        let invalid = || Error::InvalidSuite(raw.to_string());
+        let mut parts = raw.split(':');
+        let (Some(version), Some(crypto), Some(data_input), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if version != "OCRA-1" {
+            return Err(invalid());
+        }
+
+        // CryptoFunction: HOTP-SHAx-t
+        let mut crypto = crypto.split('-');
+        if crypto.next() != Some("HOTP") {
+            return Err(invalid());
+        }
+        let algo = crypto.next().and_then(parse_algo).ok_or_else(invalid)?;
+        let digits = crypto
+            .next()
+            .and_then(|digits| digits.parse::<u32>().ok())
+            .filter(|digits| (4..=10).contains(digits))
+            .ok_or_else(invalid)?;
+        if crypto.next().is_some() {
+            return Err(invalid());
+        }
+
+        // DataInput: [C] | QFxx | [PH | Snnn | TG]
+        let mut inputs = data_input.split('-').peekable();
+        let counter = inputs.next_if_eq(&"C").is_some();
+        let question = inputs.next().ok_or_else(invalid)?;
+        let question_format = match question.get(..2) {
+            Some("QN") => QuestionFormat::Numeric,
+            Some("QA") => QuestionFormat::Alphanumeric,
+            Some("QH") => QuestionFormat::Hex,
+            _ => return Err(invalid()),
+        };
+        let question_length = question[2..]
+            .parse::<usize>()
+            .ok()
+            .filter(|length| (4..=64).contains(length))
+            .ok_or_else(invalid)?;
+
+        let mut suite = Suite {
+            raw: raw.to_string(),
+            algo,
+            digits,
+            counter,
+            question_format,
+            question_length,
+            password: None,
+            session_length: None,
+            timestamp_step: None,
+        };
+        for input in inputs {
+            match input.split_at_checked(1).ok_or_else(invalid)? {
+                ("P", hash) if suite.password.is_none() => {
+                    suite.password = Some(parse_algo(hash).ok_or_else(invalid)?);
+                }
+                ("S", length) if suite.session_length.is_none() && length.len() == 3 => {
+                    suite.session_length = Some(length.parse::<usize>().map_err(|_| invalid())?);
+                }
+                ("T", step) if suite.timestamp_step.is_none() => {
+                    suite.timestamp_step = Some(parse_time_step(step).ok_or_else(invalid)?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(suite)
+    }
+
+    /// Builds the message that gets signed: the suite, a zero byte and every
+    /// input the suite asks for in their fixed sizes.
+    fn data_input(&self, inputs: &Inputs, clock: &dyn Clock) -> Result<Vec<u8>, Error> {
+        let mut message = self.raw.as_bytes().to_vec();
+        message.push(0);
+        if self.counter {
+            let counter = inputs.counter.ok_or(Error::MissingInput("counter"))?;
+            message.extend_from_slice(&counter.to_be_bytes());
+        }
+        message.extend(self.encode_question(inputs.question)?);
+        if let Some(hash) = self.password {
+            let password = inputs.password.ok_or(Error::MissingInput("password"))?;
+            message.extend_from_slice(
+                digest::digest(digest_algorithm(hash), password.as_bytes()).as_ref(),
+            );
+        }
+        if let Some(length) = self.session_length {
+            let session = inputs.session.ok_or(Error::MissingInput("session"))?;
+            if session.len() > length {
+                return Err(Error::InvalidSession(format!(
+                    "{} bytes given, suite allows {}",
+                    session.len(),
+                    length
+                )));
+            }
+            message.resize(message.len() + length - session.len(), 0);
+            message.extend_from_slice(session);
+        }
+        if let Some(step) = self.timestamp_step {
+            let timestamp = clock.now().map_err(otp::Error::from)? / step;
+            message.extend_from_slice(&timestamp.to_be_bytes());
+        }
+        Ok(message)
+    }
+
+    /// Encodes the challenge and right-pads it with zeros to 128 bytes.
+    fn encode_question(&self, question: &str) -> Result<Vec<u8>, Error> {
+        let invalid = |reason: &str| Error::InvalidQuestion(format!("{}: {}", question, reason));
+        // The suite's length is not enforced: mutual challenge-response sends
+        // the client and server challenges concatenated.
+        if question.is_empty() {
+            return Err(invalid("empty"));
+        }
+        let hex = match self.question_format {
+            QuestionFormat::Numeric => {
+                if !question.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(invalid("not numeric"));
+                }
+                decimal_to_hex(question)
+            }
+            QuestionFormat::Hex => {
+                if !question.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(invalid("not hexadecimal"));
+                }
+                question.to_ascii_uppercase()
+            }
+            QuestionFormat::Alphanumeric => {
+                if !question.bytes().all(|b| b.is_ascii_graphic()) {
+                    return Err(invalid("not alphanumeric"));
+                }
+                if question.len() > QUESTION_LENGTH {
+                    return Err(invalid("too long"));
+                }
+                let mut bytes = question.as_bytes().to_vec();
+                bytes.resize(QUESTION_LENGTH, 0);
+                return Ok(bytes);
+            }
+        };
+        if hex.len() > QUESTION_LENGTH * 2 {
+            return Err(invalid("too long"));
+        }
+        // Hex challenges are padded on the right, which also applies to a
+        // dangling nibble of an odd length challenge.
+        let mut bytes = vec![0; QUESTION_LENGTH];
+        for (i, nibble) in hex.bytes().enumerate() {
+            let value = (nibble as char).to_digit(16).unwrap() as u8;
+            bytes[i / 2] |= if i % 2 == 0 { value << 4 } else { value };
+        }
+        Ok(bytes)
+    }
+}
+
+fn parse_algo(name: &str) -> Option<Algo> {
+    match name {
+        "SHA1" => Some(Algo::SHA1),
+        "SHA256" => Some(Algo::SHA256),
+        "SHA512" => Some(Algo::SHA512),
+        _ => None,
+    }
+}
+
+fn digest_algorithm(algo: Algo) -> &'static digest::Algorithm {
+    match algo {
+        Algo::SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        Algo::SHA256 => &digest::SHA256,
+        Algo::SHA512 => &digest::SHA512,
+    }
+}
+
+/// Parses the `G` of a `TG` timestamp input into seconds, e.g. `1M` or `30S`.
+fn parse_time_step(step: &str) -> Option<u64> {
+    let (amount, unit) = step.split_at_checked(step.len().checked_sub(1)?)?;
+    let amount = amount.parse::<u64>().ok()?;
+    match unit {
+        "S" if (1..=59).contains(&amount) => Some(amount),
+        "M" if (1..=59).contains(&amount) => Some(amount * 60),
+        "H" if (1..=48).contains(&amount) => Some(amount * 3600),
+        _ => None,
+    }
+}
+
+/// Converts a decimal number of arbitrary length into upper case hex digits.
+fn decimal_to_hex(decimal: &str) -> String {
+    let mut digits: Vec<u32> = decimal.bytes().map(|b| (b - b'0') as u32).collect();
+    let mut hex = Vec::new();
+    while digits.iter().any(|&d| d != 0) {
+        let mut remainder = 0;
+        for digit in digits.iter_mut() {
+            let value = remainder * 10 + *digit;
+            *digit = value / 16;
+            remainder = value % 16;
+        }
+        hex.push(
+            std::char::from_digit(remainder, 16)
+                .unwrap()
+                .to_ascii_uppercase(),
+        );
+    }
+    if hex.is_empty() {
+        hex.push('0');
+    }
+    hex.iter().rev().collect()
+}
+
+/// Answers an OCRA challenge given an RFC4648 base32 encoded secret, the
+/// suite and the inputs it asks for. Timestamps are read from `clock`.
+pub fn make_ocra(
+    secret: &str,
+    suite: &Suite,
+    inputs: &Inputs,
+    clock: &dyn Clock,
+) -> Result<String, Error> {
+    let decoded = decode_secret(secret).map_err(otp::Error::from)?;
+    let key = hmac::Key::new(hmac_algorithm(suite.algo), &decoded);
+    let tag = hmac::sign(&key, &suite.data_input(inputs, clock)?);
+    Ok(encode_digest(tag.as_ref(), suite.digits)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{make_ocra, Error, Inputs, QuestionFormat, Suite};
+    use crate::clock::FixedClock;
+    use crate::types::Algo;
+    use data_encoding::BASE32_NOPAD;
+
+    const SEED: &[u8] = b"12345678901234567890";
+    const SEED32: &[u8] = b"12345678901234567890123456789012";
+    const SEED64: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    fn answer(seed: &[u8], suite: &str, inputs: Inputs, time: u64) -> String {
+        let suite = Suite::parse(suite).unwrap();
+        make_ocra(
+            &BASE32_NOPAD.encode(seed),
+            &suite,
+            &inputs,
+            &FixedClock(time),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_suite() {
+        let suite = Suite::parse("OCRA-1:HOTP-SHA512-8:C-QH40-PSHA256-S064-T1M").unwrap();
+        assert_eq!(suite.algo, Algo::SHA512);
+        assert_eq!(suite.digits, 8);
+        assert!(suite.counter);
+        assert_eq!(suite.question_format, QuestionFormat::Hex);
+        assert_eq!(suite.question_length, 40);
+        assert_eq!(suite.password, Some(Algo::SHA256));
+        assert_eq!(suite.session_length, Some(64));
+        assert_eq!(suite.timestamp_step, Some(60));
+
+        for invalid in [
+            "OCRA-2:HOTP-SHA1-6:QN08",
+            "OCRA-1:HOTP-MD5-6:QN08",
+            "OCRA-1:HOTP-SHA1-3:QN08",
+            "OCRA-1:HOTP-SHA1-6:QX08",
+            "OCRA-1:HOTP-SHA1-6:QN65",
+            "OCRA-1:HOTP-SHA1-6:C",
+            "OCRA-1:HOTP-SHA1-6:QN08-T60M",
+            "OCRA-1:HOTP-SHA1-6:QN08-PSHA1-PSHA1",
+            "OCRA-1:HOTP-SHA1-6",
+            "OCRA-1:HOTP-SHA1-6:QN08-",
+            "OCRA-1:HOTP-SHA1-6:QN08-é",
+            "OCRA-1:HOTP-SHA1-6:QN08-T1é",
+        ] {
+            assert!(
+                matches!(Suite::parse(invalid), Err(Error::InvalidSuite(_))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    // RFC 6287 Appendix C.1, one way challenge response
+    #[test]
+    fn one_way() {
+        let expected = [
+            "237653", "243178", "653583", "740991", "608993", "388898", "816933", "224598",
+            "750600", "294470",
+        ];
+        for (i, code) in expected.iter().enumerate() {
+            let question = i.to_string().repeat(8);
+            let inputs = Inputs {
+                question: &question,
+                ..Default::default()
+            };
+            assert_eq!(answer(SEED, "OCRA-1:HOTP-SHA1-6:QN08", inputs, 0), *code);
+        }
+
+        let expected = [
+            "65347737", "86775851", "78192410", "71565254", "10104329", "65983500", "70069104",
+            "91771096", "75011558", "08522129",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            let inputs = Inputs {
+                counter: Some(counter as u64),
+                question: "12345678",
+                password: Some("1234"),
+                session: None,
+            };
+            assert_eq!(
+                answer(SEED32, "OCRA-1:HOTP-SHA256-8:C-QN08-PSHA1", inputs, 0),
+                *code
+            );
+        }
+
+        let expected = ["83238735", "01501458", "17957585", "86776967", "86807031"];
+        for (i, code) in expected.iter().enumerate() {
+            let question = i.to_string().repeat(8);
+            let inputs = Inputs {
+                question: &question,
+                password: Some("1234"),
+                ..Default::default()
+            };
+            assert_eq!(
+                answer(SEED32, "OCRA-1:HOTP-SHA256-8:QN08-PSHA1", inputs, 0),
+                *code
+            );
+        }
+
+        let expected = [
+            "07016083", "63947962", "70123924", "25341727", "33203315", "34205738", "44343969",
+            "51946085", "20403879", "31409299",
+        ];
+        for (i, code) in expected.iter().enumerate() {
+            let question = i.to_string().repeat(8);
+            let inputs = Inputs {
+                counter: Some(i as u64),
+                question: &question,
+                ..Default::default()
+            };
+            assert_eq!(
+                answer(SEED64, "OCRA-1:HOTP-SHA512-8:C-QN08", inputs, 0),
+                *code
+            );
+        }
+
+        // The RFC's timestamp 0x132d0b6 is in minutes.
+        let expected = ["95209754", "55907591", "22048402", "24218844", "36209546"];
+        for (i, code) in expected.iter().enumerate() {
+            let question = i.to_string().repeat(8);
+            let inputs = Inputs {
+                question: &question,
+                ..Default::default()
+            };
+            let time = 0x132d0b6 * 60 + 59;
+            assert_eq!(
+                answer(SEED64, "OCRA-1:HOTP-SHA512-8:QN08-T1M", inputs, time),
+                *code
+            );
+        }
+    }
+
+    // RFC 6287 Appendix C.2, mutual challenge response
+    #[test]
+    fn mutual() {
+        let inputs = Inputs {
+            question: "CLI22220SRV11110",
+            ..Default::default()
+        };
+        assert_eq!(
+            answer(SEED32, "OCRA-1:HOTP-SHA256-8:QA08", inputs, 0),
+            "28247970"
+        );
+    }
+
+    #[test]
+    fn session() {
+        let inputs = Inputs {
+            question: "12345678",
+            session: Some(&[0xab; 64]),
+            ..Default::default()
+        };
+        assert_eq!(
+            answer(SEED, "OCRA-1:HOTP-SHA1-6:QN08-S064", inputs, 0),
+            "978255"
+        );
+    }
+
+    #[test]
+    fn missing_inputs() {
+        let secret = BASE32_NOPAD.encode(SEED);
+        let suite = Suite::parse("OCRA-1:HOTP-SHA1-6:C-QN08-S064").unwrap();
+        let mut inputs = Inputs {
+            question: "12345678",
+            ..Default::default()
+        };
+        assert!(matches!(
+            make_ocra(&secret, &suite, &inputs, &FixedClock(0)),
+            Err(Error::MissingInput("counter"))
+        ));
+        inputs.counter = Some(0);
+        assert!(matches!(
+            make_ocra(&secret, &suite, &inputs, &FixedClock(0)),
+            Err(Error::MissingInput("session"))
+        ));
+        inputs.session = Some(&[0xab; 65]);
+        assert!(matches!(
+            make_ocra(&secret, &suite, &inputs, &FixedClock(0)),
+            Err(Error::InvalidSession(_))
+        ));
+        inputs.question = "1234567A";
+        assert!(matches!(
+            make_ocra(&secret, &suite, &inputs, &FixedClock(0)),
+            Err(Error::InvalidQuestion(_))
+        ));
+    }
+}
@@ -2,11 +2,15 @@
 #![crate_name = "rust_otp"]
 #![crate_type = "lib"]
 
-use data_encoding::{DecodeError, BASE32_NOPAD};
+use data_encoding::{DecodeError, DecodeKind, BASE32_NOPAD, HEXLOWER_PERMISSIVE};
 use err_derive::Error;
 use ring::hmac;
 use std::convert::TryInto;
-use std::time::{SystemTime, SystemTimeError};
+use std::time::SystemTimeError;
+use subtle::ConstantTimeEq;
+
+use crate::clock::Clock;
+use crate::types::Algo;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -16,22 +20,82 @@ pub enum Error {
     InvalidDigest(Vec<u8>),
     #[error(display = "invalid secret provided")]
     InvalidSecret(#[error(source)] DecodeError),
+    #[error(display = "invalid number of digits provided: {}", _0)]
+    InvalidDigits(u32),
+    #[error(display = "a challenge is needed to answer this entry")]
+    ChallengeRequired,
 }
 
 /// Decodes a secret (given as an RFC4648 base32-encoded ASCII string)
 /// into a byte string
-fn decode_secret(secret: &str) -> Result<Vec<u8>, DecodeError> {
-    BASE32_NOPAD.decode(secret.as_bytes())
+pub(crate) fn decode_secret(secret: &str) -> Result<Vec<u8>, DecodeError> {
+    BASE32_NOPAD.decode(clean_secret(secret).as_bytes())
+}
+
+/// Drops the separators and padding people type or copy along with a secret.
+fn clean_secret(secret: &str) -> String {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Normalises a base32 secret as typed by a user (lower case, spaces,
+/// hyphens, `=` padding) into the canonical unpadded upper case form.
+pub fn normalize_secret(secret: &str) -> Result<String, Error> {
+    let cleaned = clean_secret(secret);
+    if cleaned.is_empty() {
+        return Err(Error::InvalidSecret(DecodeError {
+            position: 0,
+            kind: DecodeKind::Length,
+        }));
+    }
+    BASE32_NOPAD.decode(cleaned.as_bytes())?;
+    Ok(cleaned)
+}
+
+/// Converts a hex encoded secret, as hardware-token seeds are often
+/// distributed, into the canonical base32 form.
+pub fn hex_to_secret(hex: &str) -> Result<String, Error> {
+    let cleaned: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    let cleaned = cleaned
+        .strip_prefix("0x")
+        .or_else(|| cleaned.strip_prefix("0X"))
+        .unwrap_or(&cleaned);
+    let decoded = HEXLOWER_PERMISSIVE.decode(cleaned.as_bytes())?;
+    if decoded.is_empty() {
+        return Err(Error::InvalidSecret(DecodeError {
+            position: 0,
+            kind: DecodeKind::Length,
+        }));
+    }
+    Ok(BASE32_NOPAD.encode(&decoded))
+}
+
+/// Maps the `algorithm` of an OTP entry onto the matching ring HMAC algorithm.
+pub(crate) fn hmac_algorithm(algo: Algo) -> hmac::Algorithm {
+    match algo {
+        Algo::SHA1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
+        Algo::SHA256 => hmac::HMAC_SHA256,
+        Algo::SHA512 => hmac::HMAC_SHA512,
+    }
 }
 
 /// Calculates the HMAC digest for the given secret and counter.
-fn calc_digest(decoded_secret: &[u8], counter: u64) -> hmac::Tag {
-    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, decoded_secret);
+fn calc_digest(decoded_secret: &[u8], counter: u64, algo: Algo) -> hmac::Tag {
+    let key = hmac::Key::new(hmac_algorithm(algo), decoded_secret);
     hmac::sign(&key, &counter.to_be_bytes())
 }
 
-/// Encodes the HMAC digest into a 6-digit integer.
-fn encode_digest(digest: &[u8]) -> Result<u32, Error> {
+/// Characters used by Steam Guard codes, indexed by the truncated value.
+const STEAM_ALPHABET: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+/// Length of a Steam Guard code.
+const STEAM_CODE_LENGTH: usize = 5;
+
+/// Performs the dynamic truncation of the HMAC digest into a 31-bit integer.
+fn truncate_digest(digest: &[u8]) -> Result<u32, Error> {
     let offset = match digest.last() {
         Some(x) => *x & 0xf,
         None => return Err(Error::InvalidDigest(Vec::from(digest))),
@@ -40,64 +104,289 @@ fn encode_digest(digest: &[u8]) -> Result<u32, Error> {
         Ok(x) => x,
         Err(_) => return Err(Error::InvalidDigest(Vec::from(digest))),
     };
-    let code = u32::from_be_bytes(code_bytes);
-    Ok((code & 0x7fffffff) % 1_000_000)
+    Ok(u32::from_be_bytes(code_bytes) & 0x7fffffff)
 }
 
-/// Performs the [HMAC-based One-time Password Algorithm](http://en.wikipedia.org/wiki/HMAC-based_One-time_Password_Algorithm)
-/// (HOTP) given an RFC4648 base32 encoded secret, and an integer counter.
-pub fn make_hotp(secret: &str, counter: u64) -> Result<u32, Error> {
-    let decoded = decode_secret(secret)?;
-    encode_digest(calc_digest(decoded.as_slice(), counter).as_ref())
+/// Encodes the HMAC digest into a zero-padded code of `digits` decimal digits.
+pub(crate) fn encode_digest(digest: &[u8], digits: u32) -> Result<String, Error> {
+    if !(1..=10).contains(&digits) {
+        return Err(Error::InvalidDigits(digits));
+    }
+    let code = truncate_digest(digest)? as u64;
+    Ok(format!(
+        "{:0width$}",
+        code % 10u64.pow(digits),
+        width = digits as usize
+    ))
 }
 
-/// Helper function for `make_totp` to make it testable. Note that times
-/// before Unix epoch are not supported.
-fn make_totp_helper(secret: &str, time_step: u64, skew: i64, time: u64) -> Result<u32, Error> {
-    let counter = ((time as i64 + skew) as u64) / time_step;
-    make_hotp(secret, counter)
+/// Encodes the HMAC digest into a 5-character Steam Guard code.
+fn encode_steam_digest(digest: &[u8]) -> Result<String, Error> {
+    let mut code = truncate_digest(digest)? as usize;
+    let mut encoded = String::with_capacity(STEAM_CODE_LENGTH);
+    for _ in 0..STEAM_CODE_LENGTH {
+        encoded.push(STEAM_ALPHABET[code % STEAM_ALPHABET.len()] as char);
+        code /= STEAM_ALPHABET.len();
+    }
+    Ok(encoded)
+}
+
+/// Performs the [HMAC-based One-time Password Algorithm](http://en.wikipedia.org/wiki/HMAC-based_One-time_Password_Algorithm)
+/// (HOTP) given an RFC4648 base32 encoded secret, an integer counter, the
+/// HMAC algorithm to use and the number of digits of the code.
+pub fn make_hotp(secret: &str, counter: u64, algo: Algo, digits: u32) -> Result<String, Error> {
+    let decoded = decode_secret(secret)?;
+    encode_digest(calc_digest(decoded.as_slice(), counter, algo).as_ref(), digits)
 }
 
 /// Performs the [Time-based One-time Password Algorithm](http://en.wikipedia.org/wiki/Time-based_One-time_Password_Algorithm)
 /// (TOTP) given an RFC4648 base32 encoded secret, the time step in seconds,
-/// and a skew in seconds.
-pub fn make_totp(secret: &str, time_step: u64, skew: i64) -> Result<u32, Error> {
-    let now = SystemTime::now();
-    let time_since_epoch = now.duration_since(SystemTime::UNIX_EPOCH)?;
-    match make_totp_helper(secret, time_step, skew, time_since_epoch.as_secs()) {
-        Ok(d) => Ok(d),
-        Err(err) => return Err(err),
+/// the HMAC algorithm to use, the number of digits of the code and the clock
+/// to read the time from.
+pub fn make_totp(
+    secret: &str,
+    time_step: u64,
+    algo: Algo,
+    digits: u32,
+    clock: &dyn Clock,
+) -> Result<String, Error> {
+    make_hotp(secret, clock.now()? / time_step, algo, digits)
+}
+
+/// Generates a Steam Guard code, which is a SHA1 TOTP encoded into 5
+/// characters of Steam's alphabet instead of decimal digits.
+pub fn make_steam_totp(secret: &str, time_step: u64, clock: &dyn Clock) -> Result<String, Error> {
+    let decoded = decode_secret(secret)?;
+    let counter = clock.now()? / time_step;
+    encode_steam_digest(calc_digest(decoded.as_slice(), counter, Algo::SHA1).as_ref())
+}
+
+/// The time steps around the current one a TOTP verifier accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Window {
+    /// Number of steps before the current one to accept (clock drift).
+    pub behind: u64,
+    /// Number of steps after the current one to accept (clock drift).
+    pub ahead: u64,
+    /// Last step that was accepted for this secret; it and every step before
+    /// it are rejected so a code cannot be replayed.
+    pub last_used: Option<u64>,
+}
+
+/// Compares two codes without leaking where they differ.
+fn codes_match(expected: &str, given: &str) -> bool {
+    expected.as_bytes().ct_eq(given.as_bytes()).into()
+}
+
+/// Verifies a HOTP `code` against the counters `counter..=counter + look_ahead`.
+/// Returns the matching counter, the caller should resume from the one after it.
+pub fn verify_hotp(
+    secret: &str,
+    code: &str,
+    counter: u64,
+    look_ahead: u64,
+    algo: Algo,
+    digits: u32,
+) -> Result<Option<u64>, Error> {
+    let mut matched = None;
+    for candidate in counter..=counter.saturating_add(look_ahead) {
+        if codes_match(&make_hotp(secret, candidate, algo, digits)?, code) && matched.is_none() {
+            matched = Some(candidate);
+        }
     }
+    Ok(matched)
+}
+
+/// Verifies a TOTP `code` for the time of `clock`, accepting the steps allowed
+/// by `window`. Returns the matching time step, which the caller should store
+/// as `Window::last_used` to reject replays.
+pub fn verify_totp(
+    secret: &str,
+    code: &str,
+    time_step: u64,
+    window: Window,
+    algo: Algo,
+    digits: u32,
+    clock: &dyn Clock,
+) -> Result<Option<u64>, Error> {
+    let current = clock.now()? / time_step;
+    let mut matched = None;
+    for step in current.saturating_sub(window.behind)..=current.saturating_add(window.ahead) {
+        if window.last_used.is_some_and(|last| step <= last) {
+            continue;
+        }
+        if codes_match(&make_hotp(secret, step, algo, digits)?, code) && matched.is_none() {
+            matched = Some(step);
+        }
+    }
+    Ok(matched)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{make_hotp, make_totp_helper};
+    use super::{
+        hex_to_secret, make_hotp, make_steam_totp, make_totp, normalize_secret, verify_hotp,
+        verify_totp, Error, Window,
+    };
+    use crate::clock::{FixedClock, OffsetClock};
+    use crate::types::Algo;
+    use data_encoding::BASE32_NOPAD;
 
     #[test]
     fn hotp() {
-        assert_eq!(make_hotp("BASE32SECRET3232", 0).unwrap(), 260182);
-        assert_eq!(make_hotp("BASE32SECRET3232", 1).unwrap(), 55283);
-        assert_eq!(make_hotp("BASE32SECRET3232", 1401).unwrap(), 316439);
+        assert_eq!(make_hotp("BASE32SECRET3232", 0, Algo::SHA1, 6).unwrap(), "260182");
+        assert_eq!(make_hotp("BASE32SECRET3232", 1, Algo::SHA1, 6).unwrap(), "055283");
+        assert_eq!(make_hotp("BASE32SECRET3232", 1401, Algo::SHA1, 6).unwrap(), "316439");
+    }
+
+    #[test]
+    fn hotp_digits() {
+        // RFC 4226 Appendix D, counter 0: 1284755224 truncated.
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+        assert_eq!(make_hotp(&secret, 0, Algo::SHA1, 6).unwrap(), "755224");
+        assert_eq!(make_hotp(&secret, 0, Algo::SHA1, 7).unwrap(), "4755224");
+        assert_eq!(make_hotp(&secret, 0, Algo::SHA1, 8).unwrap(), "84755224");
+        assert_eq!(make_hotp(&secret, 0, Algo::SHA1, 10).unwrap(), "1284755224");
+        assert!(matches!(
+            make_hotp(&secret, 0, Algo::SHA1, 11),
+            Err(Error::InvalidDigits(11))
+        ));
+        assert!(matches!(
+            make_hotp(&secret, 0, Algo::SHA1, 0),
+            Err(Error::InvalidDigits(0))
+        ));
     }
 
     #[test]
     fn totp() {
         assert_eq!(
-            make_totp_helper("BASE32SECRET3232", 30, 0, 0).unwrap(),
-            260182
+            make_totp("BASE32SECRET3232", 30, Algo::SHA1, 6, &FixedClock(0)).unwrap(),
+            "260182"
         );
         assert_eq!(
-            make_totp_helper("BASE32SECRET3232", 3600, 0, 7).unwrap(),
-            260182
+            make_totp("BASE32SECRET3232", 3600, Algo::SHA1, 6, &FixedClock(7)).unwrap(),
+            "260182"
         );
         assert_eq!(
-            make_totp_helper("BASE32SECRET3232", 30, 0, 35).unwrap(),
-            55283
+            make_totp("BASE32SECRET3232", 30, Algo::SHA1, 6, &FixedClock(35)).unwrap(),
+            "055283"
+        );
+        let behind = OffsetClock::new(FixedClock(1403), -2);
+        assert_eq!(make_totp("BASE32SECRET3232", 1, Algo::SHA1, 6, &behind).unwrap(), "316439");
+    }
+
+    // RFC 6238 Appendix B
+    #[test]
+    fn totp_rfc6238() {
+        let sha1 = BASE32_NOPAD.encode(b"12345678901234567890");
+        let sha256 = BASE32_NOPAD.encode(b"12345678901234567890123456789012");
+        let sha512 = BASE32_NOPAD.encode(
+            b"1234567890123456789012345678901234567890123456789012345678901234",
         );
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, code1, code256, code512) in vectors {
+            let clock = FixedClock(time);
+            assert_eq!(make_totp(&sha1, 30, Algo::SHA1, 8, &clock).unwrap(), code1);
+            assert_eq!(make_totp(&sha256, 30, Algo::SHA256, 8, &clock).unwrap(), code256);
+            assert_eq!(make_totp(&sha512, 30, Algo::SHA512, 8, &clock).unwrap(), code512);
+        }
+    }
+
+    // Vectors from the ValvePython steam guard tests.
+    #[test]
+    fn steam() {
+        let secret = BASE32_NOPAD.encode(b"superdupersecret");
+        assert_eq!(make_steam_totp(&secret, 30, &FixedClock(3000030)).unwrap(), "YRGQJ");
+        assert_eq!(make_steam_totp(&secret, 30, &FixedClock(3000029)).unwrap(), "94R9D");
+        let behind = OffsetClock::new(FixedClock(3000030), -1);
+        assert_eq!(make_steam_totp(&secret, 30, &behind).unwrap(), "94R9D");
+    }
+
+    #[test]
+    fn hotp_verification() {
+        let secret = "BASE32SECRET3232";
+        assert_eq!(verify_hotp(secret, "316439", 1401, 0, Algo::SHA1, 6).unwrap(), Some(1401));
+        assert_eq!(verify_hotp(secret, "316439", 1398, 5, Algo::SHA1, 6).unwrap(), Some(1401));
+        assert_eq!(verify_hotp(secret, "316439", 1398, 2, Algo::SHA1, 6).unwrap(), None);
+        assert_eq!(verify_hotp(secret, "316439", 1402, 10, Algo::SHA1, 6).unwrap(), None);
+        assert_eq!(verify_hotp(secret, "31643", 1401, 0, Algo::SHA1, 6).unwrap(), None);
+    }
+
+    #[test]
+    fn totp_verification() {
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890123456789012");
+        let exact = Window::default();
+        let drift = Window { behind: 1, ahead: 1, last_used: None };
+        // 46119246 is the code for step 1 (time 59).
         assert_eq!(
-            make_totp_helper("BASE32SECRET3232", 1, -2, 1403).unwrap(),
-            316439
+            verify_totp(&secret, "46119246", 30, exact, Algo::SHA256, 8, &FixedClock(59)).unwrap(),
+            Some(1)
         );
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, exact, Algo::SHA256, 8, &FixedClock(60)).unwrap(),
+            None
+        );
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, drift, Algo::SHA256, 8, &FixedClock(60)).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, drift, Algo::SHA256, 8, &FixedClock(0)).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, drift, Algo::SHA256, 8, &FixedClock(90)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn totp_replay() {
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890123456789012");
+        let window = Window { behind: 1, ahead: 1, last_used: Some(1) };
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, window, Algo::SHA256, 8, &FixedClock(59)).unwrap(),
+            None
+        );
+        let window = Window { last_used: Some(0), ..window };
+        assert_eq!(
+            verify_totp(&secret, "46119246", 30, window, Algo::SHA256, 8, &FixedClock(59)).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn secret_normalization() {
+        assert_eq!(normalize_secret("BASE32SECRET3232").unwrap(), "BASE32SECRET3232");
+        assert_eq!(normalize_secret("base 32se cret 3232").unwrap(), "BASE32SECRET3232");
+        assert_eq!(normalize_secret("BASE-32SE-CRET-3232").unwrap(), "BASE32SECRET3232");
+        assert_eq!(normalize_secret("JBSWY3DPEE======").unwrap(), "JBSWY3DPEE");
+        assert_eq!(normalize_secret("\tjbsw y3dp ee==\n").unwrap(), "JBSWY3DPEE");
+        assert!(matches!(normalize_secret("JBSWY3DP1"), Err(Error::InvalidSecret(_))));
+        assert!(matches!(normalize_secret(" - "), Err(Error::InvalidSecret(_))));
+        assert_eq!(
+            make_hotp("base32 secret 3232", 0, Algo::SHA1, 6).unwrap(),
+            "260182"
+        );
+    }
+
+    #[test]
+    fn hex_secrets() {
+        let expected = BASE32_NOPAD.encode(b"12345678901234567890");
+        let hex = "3132333435363738393031323334353637383930";
+        assert_eq!(hex_to_secret(hex).unwrap(), expected);
+        assert_eq!(hex_to_secret(&format!("0x{}", hex)).unwrap(), expected);
+        assert_eq!(hex_to_secret("31323334 35363738 39303132 33343536 37383930").unwrap(), expected);
+        assert_eq!(hex_to_secret(&hex.to_uppercase()).unwrap(), expected);
+        assert!(matches!(hex_to_secret("313"), Err(Error::InvalidSecret(_))));
+        assert!(matches!(hex_to_secret("zz"), Err(Error::InvalidSecret(_))));
+        assert!(matches!(hex_to_secret(""), Err(Error::InvalidSecret(_))));
     }
 }
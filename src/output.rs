@@ -0,0 +1,145 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::clock::{Clock, FixedClock};
+use crate::types::OTP;
+
+/// How `list`, `code` and `show` print entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    /// For people to read
+    #[default]
+    Text,
+    /// One JSON document, an array for `list`
+    Json,
+    /// One line per entry with tab separated id, issuer, account, type,
+    /// code, seconds_remaining and next_code, then secret and uri when asked
+    /// for
+    Tsv,
+}
+
+/// An entry as printed for scripts. The fields are part of the output
+/// format, new ones go at the end.
+#[derive(Debug, Serialize)]
+pub struct Record {
+    id: String,
+    issuer: String,
+    account: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    code: Option<String>,
+    seconds_remaining: Option<u64>,
+    /// Code of the next period of a time based entry, for scripts that can't
+    /// wait for the current one to expire.
+    next_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uri: Option<String>,
+}
+
+impl Record {
+    /// Describes `otp` with `code` as its current code, see `current_code`.
+    /// The secret and URI are only filled in with `secrets`.
+    pub fn new(otp: &OTP, code: Option<String>, clock: &dyn Clock, secrets: bool) -> Result<Record> {
+        let next_code = match otp.period() {
+            Some(period) => Some(otp.code(&FixedClock(clock.now()? + period))?),
+            None => None,
+        };
+        Ok(Record {
+            id: otp.id().to_string(),
+            issuer: otp.issuer().to_string(),
+            account: otp.account().to_string(),
+            kind: otp.kind(),
+            code,
+            seconds_remaining: otp.seconds_remaining(clock)?,
+            next_code,
+            secret: secrets.then(|| otp.secret().to_string()),
+            uri: secrets.then(|| otp.to_uri()),
+        })
+    }
+
+    /// Name and value of each field, None for fields without a value and
+    /// the secret ones when they weren't asked for.
+    fn fields(&self) -> Vec<(&'static str, Option<String>)> {
+        let mut fields = vec![
+            ("id", Some(self.id.clone())),
+            ("issuer", Some(self.issuer.clone())),
+            ("account", Some(self.account.clone())),
+            ("type", Some(self.kind.to_string())),
+            ("code", self.code.clone()),
+            ("seconds_remaining", self.seconds_remaining.map(|seconds| seconds.to_string())),
+            ("next_code", self.next_code.clone()),
+        ];
+        if self.secret.is_some() {
+            fields.push(("secret", self.secret.clone()));
+            fields.push(("uri", self.uri.clone()));
+        }
+        fields
+    }
+
+    /// The entry as one TSV line, tabs and line breaks in values turned into
+    /// spaces.
+    pub fn tsv(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .into_iter()
+            .map(|(_, value)| value.unwrap_or_default().replace(['\t', '\n', '\r'], " "))
+            .collect();
+        fields.join("\t")
+    }
+
+    /// The entry as "field: value" lines, leaving out empty fields.
+    pub fn text(&self) -> String {
+        let lines: Vec<String> = self
+            .fields()
+            .into_iter()
+            .filter_map(|(field, value)| Some(format!("{field}: {}", value.filter(|value| !value.is_empty())?)))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Code to print for `otp` without being asked for one, only time based
+/// entries have one: showing the code of a counter based entry would hand it
+/// out without moving the counter past it.
+pub fn current_code(otp: &OTP, clock: &dyn Clock) -> Result<Option<String>> {
+    match otp.period() {
+        Some(_) => Ok(Some(otp.code(clock)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{current_code, Record};
+    use crate::clock::FixedClock;
+    use crate::types::OTP;
+
+    #[test]
+    fn records() {
+        let clock = FixedClock(59);
+        let uri = "otpauth://totp/ACME:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=ACME";
+        let totp = OTP::parse_uri(uri).unwrap();
+        let record = Record::new(&totp, current_code(&totp, &clock).unwrap(), &clock, false).unwrap();
+        let json = format!(
+            r#"{{"id":"{}","issuer":"ACME","account":"john","type":"TOTP","code":"287082","seconds_remaining":1,"next_code":"359152"}}"#,
+            totp.id()
+        );
+        assert_eq!(serde_json::to_string(&record).unwrap(), json);
+        assert_eq!(record.tsv(), format!("{}\tACME\tjohn\tTOTP\t287082\t1\t359152", totp.id()));
+        assert!(record.text().contains("account: john\ntype: TOTP\ncode: 287082"));
+
+        let record = Record::new(&totp, None, &clock, true).unwrap();
+        assert_eq!(serde_json::to_value(&record).unwrap()["secret"], "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        let uri = "otpauth://totp/ACME:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        assert!(record.tsv().ends_with(&format!("\tGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\t{uri}")));
+
+        let hotp = OTP::parse_uri("otpauth://hotp/ACME:jane?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0").unwrap();
+        assert_eq!(current_code(&hotp, &clock).unwrap(), None);
+        let record = Record::new(&hotp, None, &clock, false).unwrap();
+        assert!(record.tsv().ends_with("\tHOTP\t\t\t"));
+        assert!(serde_json::to_value(&record).unwrap()["code"].is_null());
+    }
+}
@@ -1,18 +1,39 @@
-use toml::*;
+use serde::{Deserialize, Serialize};
 use anyhow::{Ok, Result};
 use crate::types::*;
 
-pub fn deserialize(raw_string: String, list:Vec<OTP>) -> Result<()> {
-let deserialized = raw_string.parse::<Table>()?;
+/// Layout of `secrets.toml`: every entry lives in the `[secrets]` table as
+/// an array of `[[secrets.entries]]` tables.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "E: Deserialize<'de> + Default"))]
+struct SecretsFile<E> {
+    secrets: Secrets<E>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(deserialize = "E: Deserialize<'de> + Default"))]
+struct Secrets<E> {
+    #[serde(default)]
+    entries: E,
+}
 
-Ok(())
+pub fn deserialize(raw_string: &str) -> Result<Vec<OTP>> {
+    let deserialized: SecretsFile<Vec<OTP>> = toml::from_str(raw_string)?;
+    Ok(deserialized.secrets.entries)
+}
+
+pub fn serialize(list: &[OTP]) -> Result<String> {
+    let file = SecretsFile {
+        secrets: Secrets { entries: list },
+    };
+    Ok(toml::to_string(&file)?)
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::OTP;
-    use super::deserialize;
+    use crate::types::OTP;
+    use super::{deserialize, serialize};
 
 
     #[test]
@@ -21,4 +42,26 @@ mod tests {
         let otp = OTP::parse_uri(&"otpauth://totp/grwrwghwRGHRGWRGW?secret=BASE32SECRET3232&issuer=grwrwghwRGHRGWRGW&algorithm=SHA1&digits=6&period=30".to_string()).unwrap();
         println!("{:?}", otp);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn empty_secrets() {
+        assert!(deserialize("[secrets]").unwrap().is_empty());
+        assert!(deserialize("").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let uris = [
+            "otpauth://totp/ACME%20Co:john%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+            "otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&counter=7",
+            "otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam&algorithm=SHA1&digits=6&period=30&encoder=steam",
+            "otpauth://ocra/Bank:john?secret=JBSWY3DPEHPK3PXP&issuer=Bank&suite=OCRA-1%3AHOTP-SHA1-6%3AQN08&counter=0",
+        ];
+        let list: Vec<OTP> = uris.iter().map(|uri| OTP::parse_uri(uri).unwrap()).collect();
+        let serialized = serialize(&list).unwrap();
+        assert!(serialized.contains("[[secrets.entries]]"));
+        let deserialized = deserialize(&serialized).unwrap();
+        let reexported: Vec<String> = deserialized.iter().map(|otp| otp.to_uri()).collect();
+        assert_eq!(reexported, uris);
+    }
+}
@@ -0,0 +1,64 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+
+use crate::types::OTP;
+
+/// Entries matching `query`, as indices into `entries` with how well they
+/// match, best first and in database order among equals. Each word of the
+/// query has to fuzzily match the issuer, the account, a tag or the notes of
+/// an entry, case insensitively. Every entry matches a blank query.
+pub fn search(entries: &[OTP], query: &str) -> Vec<(usize, i64)> {
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut matches: Vec<(usize, i64)> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(index, otp)| Some((index, score(&matcher, otp, query)?)))
+        .collect();
+    matches.sort_by(|(_, a), (_, b)| b.cmp(a));
+    matches
+}
+
+/// Sum of the best score of each word of `query` over the fields of `otp`,
+/// None when a word matches none of them.
+fn score(matcher: &SkimMatcherV2, otp: &OTP, query: &str) -> Option<i64> {
+    query
+        .split_whitespace()
+        .map(|word| {
+            [otp.issuer(), otp.account(), otp.notes()]
+                .into_iter()
+                .chain(otp.tags().iter().map(String::as_str))
+                .filter_map(|field| matcher.fuzzy_match(field, word))
+                .max()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::search;
+    use crate::types::{Edit, OTP};
+
+    fn entry(label: &str, tags: &[&str], notes: &str) -> OTP {
+        let mut otp = OTP::parse_uri(&format!("otpauth://totp/{label}?secret=JBSWY3DPEHPK3PXP")).unwrap();
+        let tags = tags.iter().map(|tag| tag.to_string()).collect();
+        otp.edit(Edit { tags: Some(tags), notes: Some(notes.to_string()), ..Edit::default() }).unwrap();
+        otp
+    }
+
+    #[test]
+    fn matching() {
+        let entries = vec![
+            entry("GitHub:me@home.org", &["personal"], ""),
+            entry("GitLab:me@work.com", &["work"], "self-hosted, ask IT to reset"),
+            entry("Globex:john", &["work"], ""),
+        ];
+        let found = |query: &str| search(&entries, query).into_iter().map(|(index, _)| index).collect::<Vec<_>>();
+        assert_eq!(found("gith")[0], 0);
+        assert_eq!(found("GITLAB"), vec![1]);
+        assert_eq!(found("reset"), vec![1]);
+        assert_eq!(found("work"), vec![1, 2]);
+        assert_eq!(found("work lab"), vec![1]);
+        assert_eq!(found("personal john"), Vec::<usize>::new());
+        assert_eq!(found(" "), vec![0, 1, 2]);
+        assert!(found("initech").is_empty());
+    }
+}
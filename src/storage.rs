@@ -1,112 +1,594 @@
 use age::secrecy::Secret;
+use age::{Callbacks, Identity, IdentityFileEntry, Recipient};
 use anyhow::{Ok, Result};
 use inquire::{Text};
 use rpassword::*;
+use serde::{Deserialize, Serialize};
 use snafu::prelude::*;
 use std::io::Cursor;
-use std::{env, path::PathBuf};
+use std::{env, path::{Path, PathBuf}};
+use ring::digest;
 use std::{
-    env::VarError,
-    fs::File,
+    fs::{self, File, OpenOptions},
     io::{Read, Write},
+    time::SystemTime,
 };
 use tar::{Archive, Builder, Header};
 
+use crate::config::Config;
+use crate::migrate::{self, Meta, RawArchive, META_FILE};
+use crate::parse;
+use crate::types::{Edit, OTP};
+
+/// Name of the archive member holding the `[secrets]` table.
+const SECRETS_FILE: &str = "secrets.toml";
+
+/// Name of the archive member listing the team members the database is
+/// encrypted to. Absent for passphrase protected databases.
+const TEAM_FILE: &str = "team.toml";
+
+/// Directory of the archive holding entry images as
+/// `images/<entry id>/<kind>.<extension>`.
+const IMAGES_DIR: &str = "images";
+
+/// How many previous generations of the database are kept by default.
+pub const DEFAULT_BACKUPS: usize = 5;
+
 #[derive(Debug, Snafu)]
 
-enum StorageError {
-    #[snafu(display("Database could not be found by ENV var or its location: {err}"))]
+pub enum StorageError {
+    #[snafu(display("Database could not be found: {err}"))]
     DatabaseNotFound { err: String },
-    #[snafu(display("ENV var does not contain valid database name (must end with .tar.rotp): {err}"))]
+    #[snafu(display("Not a valid database name (must end with .tar.rotp): {err}"))]
     BadDBName { err: String },
     #[snafu(display("Database is invalid: {err}"))]
     InvalidDB { err: String },
+    #[snafu(display("Backup generation {generation} does not exist"))]
+    BackupNotFound { generation: usize },
+    #[snafu(display("Database {path} was changed by another process since it was opened"))]
+    ChangedOnDisk { path: String },
+    #[snafu(display("{label} was changed both here and by another process"))]
+    Conflict { label: String },
+    #[snafu(display("Database cannot be unlocked this way: {err}"))]
+    WrongKey { err: String },
+    #[snafu(display("Not an age or SSH public key: {err}"))]
+    InvalidRecipient { err: String },
+    #[snafu(display("Not an age or SSH identity file: {err}"))]
+    InvalidIdentity { err: String },
+    #[snafu(display("Team member {name} already exists"))]
+    MemberExists { name: String },
+    #[snafu(display("Team member {name} not found"))]
+    MemberNotFound { name: String },
+    #[snafu(display("No entry with id {id}"))]
+    EntryNotFound { id: String },
+    #[snafu(display("Not a supported image: {err}"))]
+    InvalidImage { err: String },
+    #[snafu(display("Could not get the password: {err}"))]
+    NoPassphrase { err: String },
+}
+
+/// Images an entry can carry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageKind {
+    /// The QR code the entry was scanned from. It holds the secret, so it is
+    /// only ever kept inside the encrypted archive.
+    Qr,
+    /// A logo of the issuer.
+    Icon,
+}
+
+impl ImageKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImageKind::Qr => "qr",
+            ImageKind::Icon => "icon",
+        }
+    }
+}
+
+/// Someone with access to a team vault: a name for humans and the age or SSH
+/// public key the vault is encrypted to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TeamMember {
+    pub name: String,
+    pub recipient: String,
+}
+
+impl TeamMember {
+    /// Names a bare recipient after the comment of an SSH key, or the key
+    /// itself when it has none.
+    pub fn from_recipient(recipient: String) -> TeamMember {
+        let name = match recipient.split_whitespace().nth(2) {
+            Some(comment) => comment.to_string(),
+            None => recipient.clone(),
+        };
+        TeamMember { name, recipient }
+    }
+}
+
+/// Layout of `team.toml`: an array of `[[members]]` tables.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TeamFile {
+    #[serde(default)]
+    members: Vec<TeamMember>,
+}
+
+/// What unlocks a database: the passphrase it was encrypted with, or age
+/// identity files (native age keys or SSH private keys) matching one of its
+/// recipients.
+#[derive(Clone)]
+pub enum Key {
+    Passphrase(Secret<String>),
+    Identities(Vec<PathBuf>),
 }
 
 pub struct DB {
     path: PathBuf,
-    archive_handle: Archive<Cursor<Vec<u8>>>,
-    name: String,
+    key: Key,
+    /// Archive members other than secrets.toml and meta.toml, written back
+    /// untouched on save.
+    members: Vec<(PathBuf, Vec<u8>)>,
+    entries: Vec<OTP>,
+    meta: Meta,
+    /// Format the file on disk was in when it was opened, if it was older than
+    /// `migrate::FORMAT_VERSION`. It is rewritten in the current one on save.
+    upgraded_from: Option<u32>,
+    /// Number of previous generations kept next to the database on save.
+    backups: usize,
+    /// SHA256 of the ciphertext as last read or written, None for a database
+    /// that doesn't exist on disk yet.
+    disk_hash: Option<Vec<u8>>,
+    /// Ids and stored form of the entries as last read or written, the base
+    /// of a merge.
+    base: Vec<(String, String)>,
 }
 impl DB {
-    pub fn new(pass: Secret<String>) {
+    /// Opens the database at `path` and parses its secrets.
+    pub fn open(path: PathBuf, key: Key) -> Result<DB> {
+        let encrypted = {
+            let _lock = DbLock::shared(&path)?;
+            fs::read(&path)?
+        };
+        let contents = DB::opendb(&encrypted, &key)?;
+        Ok(DB {
+            path,
+            key,
+            members: contents.members,
+            base: entry_versions(&contents.entries),
+            entries: contents.entries,
+            meta: contents.meta,
+            upgraded_from: contents.upgraded_from,
+            backups: DEFAULT_BACKUPS,
+            disk_hash: Some(hash(&encrypted)),
+        })
+    }
 
-        DB {
-            path: DB::get_db().unwrap(),
-            archive_handle: DB::opendb(pass).unwrap(),
-            name: DB::get_name().unwrap(),
+    /// Creates a new empty database at `path`, which must not exist yet.
+    pub fn create(path: PathBuf, pass: Secret<String>) -> Result<DB> {
+        let mut db = DB {
+            path,
+            key: Key::Passphrase(pass),
+            members: vec![],
+            entries: vec![],
+            meta: Meta::new(),
+            upgraded_from: None,
+            backups: DEFAULT_BACKUPS,
+            disk_hash: None,
+            base: vec![],
         };
+        db.save()?;
+        Ok(db)
     }
 
-    fn get_db() -> Result<PathBuf> {
-        let mut env_path = match env::var("ROTP_DB") {
-            //check if the the path exists by env var
-            std::result::Result::Ok(val) => val,
-            Err(e) => match e {
-                //let it be none
-                VarError::NotPresent => {
-                    return Err(StorageError::DatabaseNotFound { err: e.to_string() }.into())
-                }
-                VarError::NotUnicode(e) => {
-                    return Err(StorageError::DatabaseNotFound {
-                        err: e.into_string().unwrap(),
-                    }
-                    .into())
+    /// Finds the database to use, see `Config::resolve_db` for the order in
+    /// which the `--db` and `--vault` flags, `ROTP_DB` and the configuration
+    /// are consulted.
+    pub fn get_db(flag: Option<&Path>, vault: Option<&str>, config: &Config) -> Result<PathBuf> {
+        let path = match config.resolve_db(flag, vault, env::var_os("ROTP_DB").map(PathBuf::from))? {
+            Some(path) => path,
+            None => {
+                return Err(StorageError::DatabaseNotFound {
+                    err: "pass --db or --vault, set ROTP_DB or set a default in the configuration".to_string(),
                 }
+                .into())
             }
         };
-if env_path.contains(".tar.rotp"){
-    if PathBuf::from(&env_path).exists() {
-            //database found exists
-            Ok(PathBuf::from(env_path))
-        } else {
-            Err(StorageError::DatabaseNotFound {
-                err: "Does not exist in path specified by env".to_string(),
+        if !path.to_string_lossy().ends_with(".tar.rotp") {
+            return Err(StorageError::BadDBName { err: path.display().to_string() }.into());
+        }
+        if !path.exists() {
+            return Err(StorageError::DatabaseNotFound { err: format!("{} does not exist", path.display()) }.into());
+        }
+        Ok(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[OTP] {
+        &self.entries
+    }
+
+    pub fn entries_mut(&mut self) -> &mut Vec<OTP> {
+        &mut self.entries
+    }
+
+    pub fn add(&mut self, otp: OTP) {
+        self.entries.push(otp);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<OTP> {
+        (index < self.entries.len()).then(|| self.entries.remove(index))
+    }
+
+    /// The entry with the given id.
+    pub fn find(&self, id: &str) -> Option<&OTP> {
+        self.entries.iter().find(|otp| otp.id() == id)
+    }
+
+    /// Stores an image for the entry `id` in its original encoding, replacing
+    /// the previous one of the same kind.
+    pub fn set_image(&mut self, id: &str, kind: ImageKind, data: Vec<u8>) -> Result<()> {
+        if self.find(id).is_none() {
+            return Err(StorageError::EntryNotFound { id: id.to_string() }.into());
+        }
+        let format = image::guess_format(&data).map_err(|e| StorageError::InvalidImage { err: e.to_string() })?;
+        let extension = format.extensions_str().first().copied().unwrap_or("img");
+        self.remove_image(id, kind);
+        let name = Path::new(IMAGES_DIR).join(id).join(format!("{}.{}", kind.as_str(), extension));
+        self.members.push((name, data));
+        Ok(())
+    }
+
+    /// The image of the given kind stored for the entry `id`.
+    pub fn image(&self, id: &str, kind: ImageKind) -> Option<&[u8]> {
+        self.members
+            .iter()
+            .find(|(name, _)| image_owner(name) == Some((id, kind.as_str())))
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// Drops the image of the given kind stored for the entry `id`, returns
+    /// whether there was one.
+    pub fn remove_image(&mut self, id: &str, kind: ImageKind) -> bool {
+        let before = self.members.len();
+        self.members.retain(|(name, _)| image_owner(name) != Some((id, kind.as_str())));
+        self.members.len() != before
+    }
+
+    /// Drops the images of entries that no longer exist, returns how many.
+    /// Runs on every save so removed entries don't leave their QR codes behind.
+    pub fn gc_images(&mut self) -> usize {
+        let before = self.members.len();
+        let entries = &self.entries;
+        self.members.retain(|(name, _)| match image_owner(name) {
+            Some((id, _)) => entries.iter().any(|otp| otp.id() == id),
+            None => true,
+        });
+        before - self.members.len()
+    }
+
+    /// Sets how many previous generations are kept on save, 0 disables backups.
+    pub fn set_backups(&mut self, backups: usize) {
+        self.backups = backups;
+    }
+
+    /// Rebuilds the archive from the current entries, encrypts it and writes
+    /// it back to the database file, keeping the previous file as a backup.
+    /// Fails with `StorageError::ChangedOnDisk` when another process saved
+    /// the database since it was opened, see `reload_and_merge`.
+    pub fn save(&mut self) -> Result<()> {
+        self.gc_images();
+        let secrets = parse::serialize(&self.entries)?;
+        let meta = self.meta.rewritten();
+        let archive = build_archive(&meta, &secrets, &self.members)?;
+        let encrypted = self.seal(archive)?;
+
+        let _lock = DbLock::exclusive(&self.path)?;
+        if self.read_disk_hash()? != self.disk_hash {
+            return Err(StorageError::ChangedOnDisk { path: self.path.display().to_string() }.into());
+        }
+        rotate_backups(&self.path, self.backups)?;
+        write_atomic(&self.path, &encrypted)?;
+        self.disk_hash = Some(hash(&encrypted));
+        self.base = entry_versions(&self.entries);
+        self.meta = meta;
+        self.upgraded_from = None;
+        Ok(())
+    }
+
+    /// Hashes the database file as it is on disk now, None when it doesn't
+    /// exist. The caller holds the lock.
+    fn read_disk_hash(&self) -> Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            std::result::Result::Ok(data) => Ok(Some(hash(&data))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether another process saved the database since it was last read or
+    /// written here.
+    pub fn changed_on_disk(&self) -> Result<bool> {
+        let _lock = DbLock::shared(&self.path)?;
+        Ok(self.read_disk_hash()? != self.disk_hash)
+    }
+
+    /// Reads the database again, dropping the changes made here.
+    pub fn reload(&mut self) -> Result<()> {
+        *self = DB { backups: self.backups, ..DB::open(self.path.clone(), self.key.clone())? };
+        Ok(())
+    }
+
+    /// Makes a code of the counter based entry `id` with `code` and saves the
+    /// entry moved past it. The code is always made from the counter on disk:
+    /// when another process saved the database in the meantime it is reloaded,
+    /// dropping the changes made here, and the code made again, so a code is
+    /// never handed out twice. Counters are never merged.
+    pub fn use_counter<T>(&mut self, id: &str, mut code: impl FnMut(&OTP) -> Result<T>) -> Result<T> {
+        loop {
+            if self.changed_on_disk()? {
+                self.reload()?;
+            }
+            let Some(index) = self.entries.iter().position(|otp| otp.id() == id) else {
+                return Err(StorageError::EntryNotFound { id: id.to_string() }.into());
+            };
+            let made = code(&self.entries[index])?;
+            self.entries[index].increment_counter();
+            match self.save() {
+                Err(err) if matches!(err.downcast_ref::<StorageError>(), Some(StorageError::ChangedOnDisk { .. })) => {}
+                saved => return saved.map(|()| made),
             }
-            .into())
-        }} else {
-            Err(StorageError::BadDBName {err : format!("{}",env_path)}.into())
         }
     }
 
-    pub fn get_name() -> Result<String> {
-    match env::var("ROTP_DB") {
-            //check if the the path exists by env var
-            std::result::Result::Ok(val) => Ok(val),
-            Err(e) => match e {
-                //let it be none
-                VarError::NotPresent => {
-                    return Err(StorageError::DatabaseNotFound { err: e.to_string() }.into())
-                }
-                VarError::NotUnicode(e) => {
-                    return Err(StorageError::DatabaseNotFound {
-                        err: e.into_string().unwrap(),
-                    }
-                    .into())
+    /// Reloads a database that changed on disk and replays the changes made
+    /// here since it was opened, matching entries by id: entries added here
+    /// are added, entries removed here are removed, entries changed here keep
+    /// the change and everything else comes from disk. An entry changed both
+    /// here and on disk fails with `StorageError::Conflict`, unless only its
+    /// counter moved on both sides, then the higher counter is kept.
+    pub fn reload_and_merge(&mut self) -> Result<()> {
+        let encrypted = {
+            let _lock = DbLock::shared(&self.path)?;
+            fs::read(&self.path)?
+        };
+        let contents = DB::opendb(&encrypted, &self.key)?;
+        let disk_versions = entry_versions(&contents.entries);
+        let base_version = |id: &str| self.base.iter().find(|(base_id, _)| base_id == id).map(|(_, version)| version);
+        let mut local = self.entries.clone();
+        let mut merged = vec![];
+        for otp in contents.entries {
+            match local.iter().position(|mine| mine.id() == otp.id()) {
+                Some(index) => {
+                    let mine = local.remove(index);
+                    let base = base_version(mine.id());
+                    let changed_here = base != Some(&entry_version(&mine));
+                    let changed_there = base != Some(&entry_version(&otp));
+                    merged.push(match (changed_here, changed_there) {
+                        (true, true) => merge_entry(mine, otp)?,
+                        (true, false) => mine,
+                        (false, _) => otp,
+                    });
                 }
+                None if base_version(otp.id()).is_some() => {}
+                None => merged.push(otp),
+            }
+        }
+        // what is left was either added here or removed on disk
+        merged.extend(local.into_iter().filter(|mine| base_version(mine.id()).is_none()));
+        self.entries = merged;
+        self.base = disk_versions;
+        self.meta = contents.meta;
+        for (name, data) in contents.members {
+            if image_owner(&name).is_some() && !self.members.iter().any(|(local, _)| *local == name) {
+                self.members.push((name, data));
+            }
+        }
+        self.disk_hash = Some(hash(&encrypted));
+        Ok(())
+    }
+
+    /// The team members the database is encrypted to, read from the manifest
+    /// inside the archive. Empty when it is protected by a passphrase.
+    pub fn team(&self) -> Result<Vec<TeamMember>> {
+        match self.members.iter().find(|(name, _)| name == Path::new(TEAM_FILE)) {
+            Some((_, data)) => Ok(toml::from_str::<TeamFile>(std::str::from_utf8(data)?)?.members),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The age recipients the database is encrypted to, empty when it is
+    /// protected by a passphrase.
+    pub fn recipients(&self) -> Result<Vec<String>> {
+        Ok(self.team()?.into_iter().map(|member| member.recipient).collect())
+    }
+
+    /// Encrypts the database to `recipients` from the next save on, naming
+    /// them with `TeamMember::from_recipient`. See `set_team`.
+    pub fn encrypt_to_recipients(&mut self, recipients: Vec<String>, identities: Vec<PathBuf>) -> Result<()> {
+        self.set_team(recipients.into_iter().map(TeamMember::from_recipient).collect(), identities)
+    }
+
+    /// Encrypts the database to the keys of `team` from the next save on, to
+    /// be unlocked with `identities` afterwards. A test file is encrypted first
+    /// and must decrypt with `identities`, so one can't lock themselves out.
+    pub fn set_team(&mut self, team: Vec<TeamMember>, identities: Vec<PathBuf>) -> Result<()> {
+        if team.is_empty() {
+            return Err(StorageError::InvalidRecipient { err: "no recipients given".to_string() }.into());
+        }
+        for (i, member) in team.iter().enumerate() {
+            if team[..i].iter().any(|other| other.name == member.name) {
+                return Err(StorageError::MemberExists { name: member.name.clone() }.into());
             }
         }
+        let recipients: Vec<String> = team.iter().map(|member| member.recipient.clone()).collect();
+        let key = Key::Identities(identities);
+        let probe = DB::encrypt_for(&recipients, b"rotp".to_vec())?;
+        DB::decrypt(&key, probe.as_slice())?;
+        let manifest = toml::to_string(&TeamFile { members: team })?;
+        self.members.retain(|(name, _)| name != Path::new(TEAM_FILE));
+        self.members.push((PathBuf::from(TEAM_FILE), manifest.into_bytes()));
+        self.key = key;
+        Ok(())
+    }
+
+    /// Gives `member` access from the next save on. `identities` must still
+    /// unlock the database, see `set_team`.
+    pub fn add_member(&mut self, member: TeamMember, identities: Vec<PathBuf>) -> Result<()> {
+        let mut team = self.team()?;
+        if team.iter().any(|other| other.name == member.name) {
+            return Err(StorageError::MemberExists { name: member.name }.into());
+        }
+        team.push(member);
+        self.set_team(team, identities)
+    }
+
+    /// Revokes the access of the member called `name` from the next save on.
+    /// They keep access to copies and backups made before, so the secrets they
+    /// could see should be rotated.
+    pub fn remove_member(&mut self, name: &str, identities: Vec<PathBuf>) -> Result<TeamMember> {
+        let mut team = self.team()?;
+        let index = match team.iter().position(|member| member.name == name) {
+            Some(index) => index,
+            None => return Err(StorageError::MemberNotFound { name: name.to_string() }.into()),
+        };
+        let removed = team.remove(index);
+        self.set_team(team, identities)?;
+        Ok(removed)
+    }
+
+    /// Protects the database with `pass` instead of recipients from the next
+    /// save on.
+    pub fn encrypt_to_passphrase(&mut self, pass: Secret<String>) {
+        self.members.retain(|(name, _)| name != Path::new(TEAM_FILE));
+        self.key = Key::Passphrase(pass);
+    }
+
+    /// Whether the database at `path` is protected by a passphrase rather
+    /// than encrypted to recipients, read from the age header.
+    pub fn needs_passphrase(path: &Path) -> Result<bool> {
+        let _lock = DbLock::shared(path)?;
+        Ok(matches!(age::Decryptor::new(File::open(path)?)?, age::Decryptor::Passphrase(_)))
+    }
+
+    /// Encrypts an archive to the recipients if there are any, with the
+    /// passphrase otherwise.
+    fn seal(&self, archive: Vec<u8>) -> Result<Vec<u8>> {
+        let recipients = self.recipients()?;
+        if !recipients.is_empty() {
+            return DB::encrypt_for(&recipients, archive);
+        }
+        match &self.key {
+            Key::Passphrase(pass) => DB::encrypt(pass.clone(), archive),
+            Key::Identities(_) => Err(StorageError::WrongKey { err: "no passphrase or recipients to encrypt to".to_string() }.into()),
+        }
     }
 
-    fn opendb(pass: Secret<String>) -> Result<Archive<Cursor<Vec<u8>>>> { //as of now, this will only pass out the data from the secrets file, in the future there may be images inside too
-        let mut encrypted_archive = File::open(DB::get_db()?.as_path()).unwrap(); //opens a file handle than opens a archine handle
-        let mut decrypted_archive = DB::decrypt(pass, std::io::BufReader::new(encrypted_archive)).map_err(||);
+    /// Metadata of the database, as it will be written on the next save.
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    /// The older format the database was upgraded from when opened, until it
+    /// is saved.
+    pub fn upgraded_from(&self) -> Option<u32> {
+        self.upgraded_from
+    }
+
+    /// Decrypts the archive, reads out meta.toml, secrets.toml and all other
+    /// members, and upgrades them to the current format.
+    fn opendb(encrypted: &[u8], key: &Key) -> Result<Contents> {
+        let decrypted_archive = DB::decrypt(key, encrypted)?;
 
         //now we can get a tar handle
-        let mut archive = Archive::new(std::io::Cursor::new(decrypted_archive));
-        //confirm the secrets file exists
-        let mut secrets_found = false;
+        let mut archive = Archive::new(Cursor::new(decrypted_archive));
+        let mut secrets = None;
+        let mut meta = None;
+        let mut members = vec![];
         for entry in archive.entries()? {
-            if entry.unwrap().path().unwrap().to_str().unwrap() == "secrets.toml" {
-                secrets_found = true;
+            let mut entry = entry?;
+            let name = entry.path()?.into_owned();
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            if name == Path::new(SECRETS_FILE) {
+                secrets = Some(data);
+            } else if name == Path::new(META_FILE) {
+                meta = Some(toml::from_str::<Meta>(std::str::from_utf8(&data)?)?);
+            } else {
+                members.push((name, data));
+            }
+        }
+        let secrets = match secrets {
+            Some(secrets) => secrets,
+            None => {
+                return Err(StorageError::InvalidDB { err: "secrets.toml not found".to_string() }.into())
+            }
+        };
+        let meta = meta.unwrap_or_else(Meta::unversioned);
+        let mut raw = RawArchive { secrets: String::from_utf8(secrets)?, members };
+        migrate::upgrade(meta.format_version, &mut raw)?;
+        let upgraded_from = (meta.format_version < migrate::FORMAT_VERSION).then_some(meta.format_version);
+        Ok(Contents {
+            entries: parse::deserialize(&raw.secrets)?,
+            members: raw.members,
+            meta: meta.rewritten(),
+            upgraded_from,
+        })
+    }
+
+    /// Lists the backups kept next to the database at `path`, newest first.
+    pub fn list_backups(path: &Path) -> Result<Vec<Backup>> {
+        let mut backups = vec![];
+        let mut generation = 1;
+        loop {
+            let backup = backup_path(path, generation);
+            match fs::metadata(&backup) {
+                std::result::Result::Ok(metadata) => backups.push(Backup {
+                    generation,
+                    path: backup,
+                    modified: metadata.modified()?,
+                }),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
             }
+            generation += 1;
         }
-        if !secrets_found {
-            return Err(StorageError::InvalidDB { err: "secrets.toml not found".to_string() }.into())
+        Ok(backups)
+    }
+
+    /// Replaces the database at `path` with a backup generation after checking
+    /// it decrypts with `pass`. The replaced database becomes generation 1, so
+    /// a restore can be undone.
+    pub fn restore_backup(path: &Path, generation: usize, key: Key, backups: usize) -> Result<()> {
+        let backup = backup_path(path, generation);
+        if !backup.exists() {
+            return Err(StorageError::BackupNotFound { generation }.into());
         }
-       Ok((archive))
+        let encrypted = fs::read(&backup)?;
+        DB::opendb(&encrypted, &key)?;
+        let _lock = DbLock::exclusive(path)?;
+        rotate_backups(path, backups.max(1))?;
+        write_atomic(path, &encrypted)
     }
-    fn encrypt(pass: Secret<String>, data: Vec<u8>) -> Result<(Vec<u8>)> {
+
+    /// Re-encrypts the database at `path` from `old` to `new` passphrase. The
+    /// archive is carried over byte for byte and the new ciphertext is checked
+    /// to decrypt back to it before it replaces the file. The previous file is
+    /// kept as backup 1, still encrypted with the old passphrase.
+    pub fn rekey(path: &Path, old: Secret<String>, new: Secret<String>, backups: usize) -> Result<()> {
+        let _lock = DbLock::exclusive(path)?;
+        let archive = DB::decrypt(&Key::Passphrase(old), fs::read(path)?.as_slice())?;
+        let encrypted = DB::encrypt(new.clone(), archive.clone())?;
+        if DB::decrypt(&Key::Passphrase(new), encrypted.as_slice())? != archive {
+            return Err(StorageError::InvalidDB { err: "re-encrypted database does not match".to_string() }.into());
+        }
+        rotate_backups(path, backups)?;
+        write_atomic(path, &encrypted)
+    }
+
+    fn encrypt(pass: Secret<String>, data: Vec<u8>) -> Result<Vec<u8>> {
         //consumes the password
         let mut encrypted_data = vec![];
         let mut writer = age::Encryptor::with_user_passphrase(pass).wrap_output(&mut encrypted_data)?;
@@ -116,31 +598,274 @@ if env_path.contains(".tar.rotp"){
         Ok(encrypted_data)
     }
 
-    fn extract_secrets(&mut self) -> Result<(String)> {
-        let mut secret_buff: String = String::new();
-        for entry in self.archive_handle.entries()? {
-            if entry.as_ref().unwrap().path().unwrap().to_str().unwrap() == "secrets.toml" {
-                entry?.read_to_string(&mut secret_buff)?;
+    fn encrypt_for(recipients: &[String], data: Vec<u8>) -> Result<Vec<u8>> {
+        let recipients = recipients
+            .iter()
+            .map(|recipient| parse_recipient(recipient))
+            .collect::<Result<Vec<_>>>()?;
+        let encryptor = match age::Encryptor::with_recipients(recipients) {
+            Some(encryptor) => encryptor,
+            None => return Err(StorageError::InvalidRecipient { err: "no recipients given".to_string() }.into()),
+        };
+        let mut encrypted_data = vec![];
+        let mut writer = encryptor.wrap_output(&mut encrypted_data)?;
+        writer.write_all(&data)?;
+        writer.finish()?;
+
+        Ok(encrypted_data)
+    }
+
+    fn decrypt<R: Read>(key: &Key, data: R) -> Result<Vec<u8>> {
+        let mut decrypted = vec![];
+        match (age::Decryptor::new(data)?, key) {
+            (age::Decryptor::Passphrase(d), Key::Passphrase(pass)) => {
+                d.decrypt(pass, None)?.read_to_end(&mut decrypted)?;
+            }
+            (age::Decryptor::Recipients(d), Key::Identities(files)) => {
+                let identities = load_identities(files)?;
+                d.decrypt(identities.iter().map(|identity| identity.as_ref()))?
+                    .read_to_end(&mut decrypted)?;
+            }
+            (age::Decryptor::Passphrase(_), Key::Identities(_)) => {
+                return Err(StorageError::WrongKey { err: "it is protected by a passphrase".to_string() }.into())
+            }
+            (age::Decryptor::Recipients(_), Key::Passphrase(_)) => {
+                return Err(StorageError::WrongKey { err: "it is encrypted to age recipients, pass an identity file".to_string() }.into())
             }
         }
 
-        Ok((secret_buff))
+        Ok(decrypted)
     }
-    
-    fn decrypt(pass: Secret<String>, data: std::io::BufReader<File>) -> Result<Vec<u8>> {
-        let mut decrypted_data: Vec<u8> = vec![];
-        let mut writer = match age::Decryptor::new(data)? {
-            age::Decryptor::Passphrase(d) => d,
-            _ => unreachable!(),
+}
+
+/// What a decrypted archive holds, upgraded to the current format.
+struct Contents {
+    entries: Vec<OTP>,
+    members: Vec<(PathBuf, Vec<u8>)>,
+    meta: Meta,
+    upgraded_from: Option<u32>,
+}
+
+/// Builds the tar archive holding meta.toml, secrets.toml and any other
+/// members.
+fn build_archive(meta: &Meta, secrets: &str, members: &[(PathBuf, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut builder = Builder::new(vec![]);
+    let meta = toml::to_string(meta)?;
+    builder.append(&create_tar_header(META_FILE, meta.len() as u64), meta.as_bytes())?;
+    builder.append(&create_tar_header(SECRETS_FILE, secrets.len() as u64), secrets.as_bytes())?;
+    for (name, data) in members {
+        builder.append(&create_tar_header(name, data.len() as u64), data.as_slice())?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Parses an age X25519 recipient (`age1...`) or an SSH public key
+/// (`ssh-ed25519 ...`, `ssh-rsa ...`).
+fn parse_recipient(recipient: &str) -> Result<Box<dyn Recipient + Send>> {
+    if let std::result::Result::Ok(recipient) = recipient.parse::<age::x25519::Recipient>() {
+        return Ok(Box::new(recipient));
+    }
+    match recipient.parse::<age::ssh::Recipient>() {
+        std::result::Result::Ok(recipient) => Ok(Box::new(recipient)),
+        Err(_) => Err(StorageError::InvalidRecipient { err: recipient.to_string() }.into()),
+    }
+}
+
+/// Reads recipients in the format of `age -R`: one per line, blank lines and
+/// `#` comments ignored.
+pub fn parse_recipients(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Loads age identity files and SSH private keys. Passphrase protected SSH
+/// keys ask for their passphrase when they are used.
+fn load_identities(files: &[PathBuf]) -> Result<Vec<Box<dyn Identity>>> {
+    if files.is_empty() {
+        return Err(StorageError::WrongKey { err: "no identity files given".to_string() }.into());
+    }
+    let mut identities: Vec<Box<dyn Identity>> = vec![];
+    for file in files {
+        let contents = fs::read(file)?;
+        let name = file.display().to_string();
+        match age::ssh::Identity::from_buffer(contents.as_slice(), Some(name.clone())) {
+            std::result::Result::Ok(age::ssh::Identity::Unsupported(_)) => {
+                return Err(StorageError::InvalidIdentity { err: format!("{name} is an unsupported SSH key") }.into())
+            }
+            std::result::Result::Ok(identity) => identities.push(Box::new(identity.with_callbacks(PromptCallbacks))),
+            Err(_) => {
+                let entries = age::IdentityFile::from_buffer(contents.as_slice())
+                    .map_err(|_| StorageError::InvalidIdentity { err: name.clone() })?
+                    .into_identities();
+                if entries.is_empty() {
+                    return Err(StorageError::InvalidIdentity { err: name }.into());
+                }
+                for entry in entries {
+                    match entry {
+                        IdentityFileEntry::Native(identity) => identities.push(Box::new(identity)),
+                    }
+                }
+            }
+        }
+    }
+    Ok(identities)
+}
+
+/// Asks on the terminal for the passphrase of encrypted SSH keys.
+#[derive(Clone, Copy)]
+struct PromptCallbacks;
+
+impl Callbacks for PromptCallbacks {
+    fn display_message(&self, message: &str) {
+        eprintln!("{message}");
+    }
+
+    fn confirm(&self, _message: &str, _yes_string: &str, _no_string: Option<&str>) -> Option<bool> {
+        None
+    }
+
+    fn request_public_string(&self, _description: &str) -> Option<String> {
+        None
+    }
+
+    fn request_passphrase(&self, description: &str) -> Option<Secret<String>> {
+        prompt_password(format!("{description}: ")).ok().map(Secret::new)
+    }
+}
+
+/// Entry id and image kind of an `images/<id>/<kind>.<extension>` member.
+fn image_owner(name: &Path) -> Option<(&str, &str)> {
+    let rest = name.strip_prefix(IMAGES_DIR).ok()?;
+    let mut components = rest.iter();
+    let id = components.next()?.to_str()?;
+    let kind = Path::new(components.next()?).file_stem()?.to_str()?;
+    components.next().is_none().then_some((id, kind))
+}
+
+fn hash(data: &[u8]) -> Vec<u8> {
+    digest::digest(&digest::SHA256, data).as_ref().to_vec()
+}
+
+fn entry_versions(entries: &[OTP]) -> Vec<(String, String)> {
+    entries.iter().map(|otp| (otp.id().to_string(), entry_version(otp))).collect()
+}
+
+/// Everything stored for an entry, tags and notes included, to tell whether
+/// it changed.
+fn entry_version(otp: &OTP) -> String {
+    serde_json::to_string(otp).expect("entries serialize to JSON")
+}
+
+/// Merges an entry that changed both here and on disk. Only a counter that
+/// moved on both sides can be merged, by keeping the higher one.
+fn merge_entry(mut mine: OTP, mut theirs: OTP) -> Result<OTP> {
+    if let (Some(here), Some(there)) = (mine.counter(), theirs.counter()) {
+        let counter = || Edit { counter: Some(here.max(there)), ..Default::default() };
+        mine.edit(counter())?;
+        theirs.edit(counter())?;
+    }
+    if entry_version(&mine) != entry_version(&theirs) {
+        return Err(StorageError::Conflict { label: mine.to_string() }.into());
+    }
+    Ok(mine)
+}
+
+/// Advisory lock on `<db>.lock`, held while the database is read or written
+/// so concurrent ROTP processes don't interleave. The database file itself
+/// can't carry the lock since saving replaces it. Released when dropped.
+struct DbLock(Option<File>);
+
+impl DbLock {
+    fn open(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new().create(true).truncate(false).write(true).open(DbLock::path(path))
+    }
+
+    fn path(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".lock");
+        PathBuf::from(name)
+    }
+
+    /// Readers of a database in a read-only directory lock an existing lock
+    /// file read-only, or go without a lock when there is none.
+    fn shared(path: &Path) -> Result<DbLock> {
+        let Some(file) = DbLock::open(path).or_else(|_| File::open(DbLock::path(path))).ok() else {
+            return Ok(DbLock(None));
         };
-        let mut decrypted = vec![];
-        let mut reader = writer.decrypt(&pass, None)?;
-        reader.read_to_end(&mut decrypted);
-    
-        Ok(decrypted)
+        file.lock_shared()?;
+        Ok(DbLock(Some(file)))
+    }
+
+    fn exclusive(path: &Path) -> Result<DbLock> {
+        let file = DbLock::open(path)?;
+        file.lock()?;
+        Ok(DbLock(Some(file)))
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        if let Some(file) = &self.0 {
+            let _ = file.unlock();
+        }
     }
 }
 
+/// A previous generation of the database kept by `DB::save`.
+#[derive(Debug)]
+pub struct Backup {
+    pub generation: usize,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+/// Backups live next to the database as `<db>.<generation>.bak`, 1 being the newest.
+fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}.bak", generation));
+    PathBuf::from(name)
+}
+
+/// Shifts the existing backups one generation back, dropping the oldest of
+/// `keep`, and copies the current database file into generation 1.
+fn rotate_backups(path: &Path, keep: usize) -> Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+    match fs::remove_file(backup_path(path, keep)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    for generation in (1..keep).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+    write_atomic(&backup_path(path, 1), &fs::read(path)?)
+}
+
+/// Writes `data` to a temporary file next to `path`, syncs it to disk and
+/// renames it over `path`, so a crash leaves either the old or the new file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    temp.write_all(data)?;
+    temp.as_file().sync_all()?;
+    temp.persist(path)?;
+    // make the rename itself durable
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
 pub fn get_pass(dir: &String) -> Result<(Secret<String>)> {
     let mut pass = rpassword::prompt_password(format!(
         "Please enter password to unlock database located at {}: ",
@@ -149,69 +874,175 @@ pub fn get_pass(dir: &String) -> Result<(Secret<String>)> {
     Ok(Secret::new(pass))
 }
 
+/// Variable a passphrase can be passed in, see `PassSource::Env`.
+pub const PASSPHRASE_ENV: &str = "ROTP_PASSPHRASE";
+
+/// Where the passphrase unlocking a database comes from, so ROTP can run
+/// without a terminal. Databases encrypted to recipients are unlocked with
+/// identity files instead and don't need one.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PassSource {
+    /// Typed in at the terminal.
+    #[default]
+    Prompt,
+    /// First line read from an open file descriptor.
+    Fd(i32),
+    /// First line of a file.
+    File(PathBuf),
+    /// First line printed by a shell command, e.g. `pass show rotp`.
+    Command(String),
+    /// The `ROTP_PASSPHRASE` variable. It can leak to child processes and
+    /// crash reports, so using it warns.
+    Env,
+}
+
+impl PassSource {
+    /// Picks the source to use: the one given on the command line, then
+    /// `ROTP_PASSPHRASE` when it is set, then the configured command, and the
+    /// terminal otherwise.
+    pub fn pick(
+        fd: Option<i32>,
+        file: Option<PathBuf>,
+        command: Option<String>,
+        env_set: bool,
+        configured: Option<&str>,
+    ) -> PassSource {
+        match (fd, file, command) {
+            (Some(fd), _, _) => PassSource::Fd(fd),
+            (None, Some(file), _) => PassSource::File(file),
+            (None, None, Some(command)) => PassSource::Command(command),
+            (None, None, None) if env_set => PassSource::Env,
+            (None, None, None) => match configured {
+                Some(command) => PassSource::Command(command.to_string()),
+                None => PassSource::Prompt,
+            },
+        }
+    }
+
+    /// Reads the passphrase of the database at `dir` from this source.
+    pub fn read(&self, dir: &String) -> Result<Secret<String>> {
+        let raw = match self {
+            PassSource::Prompt => return get_pass(dir),
+            PassSource::Fd(fd) => read_fd_line(*fd)
+                .map_err(|e| StorageError::NoPassphrase { err: format!("file descriptor {fd}: {e}") })?,
+            PassSource::File(path) => {
+                warn_if_shared(path)?;
+                fs::read_to_string(path)
+                    .map_err(|e| StorageError::NoPassphrase { err: format!("{}: {e}", path.display()) })?
+            }
+            PassSource::Command(command) => {
+                let output = std::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stderr(std::process::Stdio::inherit())
+                    .output()
+                    .map_err(|e| StorageError::NoPassphrase { err: format!("could not run {command}: {e}") })?;
+                if !output.status.success() {
+                    let err = format!("{command} failed with {}", output.status);
+                    return Err(StorageError::NoPassphrase { err }.into());
+                }
+                String::from_utf8(output.stdout)
+                    .map_err(|_| StorageError::NoPassphrase { err: format!("{command} printed invalid UTF-8") })?
+            }
+            PassSource::Env => {
+                eprintln!(
+                    "Warning: taking the password from {PASSPHRASE_ENV}, which other programs run from this \
+                     environment can read. Prefer --passphrase-fd, --passphrase-file or --passphrase-cmd."
+                );
+                env::var(PASSPHRASE_ENV)
+                    .map_err(|e| StorageError::NoPassphrase { err: format!("{PASSPHRASE_ENV}: {e}") })?
+            }
+        };
+        let pass = raw.lines().next().unwrap_or_default();
+        if pass.is_empty() {
+            return Err(StorageError::NoPassphrase { err: format!("it is empty for {dir}") }.into());
+        }
+        Ok(Secret::new(pass.to_string()))
+    }
+}
+
+/// Reads the first line from the open file descriptor `fd`, without waiting
+/// for the end of what is written to it.
+#[cfg(unix)]
+fn read_fd_line(fd: i32) -> std::io::Result<String> {
+    use std::io::{BufRead, BufReader};
+    use std::os::fd::BorrowedFd;
+    // SAFETY: the descriptor is only borrowed to duplicate it, which fails
+    // with EBADF when it isn't open.
+    let file = File::from(unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?);
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line)?;
+    std::result::Result::Ok(line)
+}
 
+#[cfg(not(unix))]
+fn read_fd_line(_fd: i32) -> std::io::Result<String> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// Warns when a password file can be read by other users.
+fn warn_if_shared(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            eprintln!(
+                "Warning: {} can be read by other users (mode {:o}), restrict it with chmod 600",
+                path.display(),
+                mode & 0o777
+            );
+        }
+    }
+    Ok(())
+}
+
+
+
+/// Asks for a new database password twice until both entries match.
+pub fn prompt_new_pass() -> Result<Secret<String>> {
+    loop {
+        let pass_prompt = prompt_password(" !!! Please input the database password: ")?;
+        let verification_prompt = prompt_password(" !!! Please verify the database password: ")?;
+        if pass_prompt != verification_prompt {
+            println!("Verification failed, try again");
+            continue;
+        }
+        return Ok(Secret::new(pass_prompt));
+    }
+}
 
 //onboarding just basicly gonna sit at like rotp init command, and will exit, basicly created a encrypted tar file with a secrets.toml and a [secrets] entry in there
 pub fn onboarding() -> Result<(bool)>{
 println!("Hello user, welcome to ROTP onboarding");
-let mut archive = vec![];
-let mut builder = Builder::new(archive);
-let secrets_data = Cursor::new("[secrets]");
-builder.append(&create_tar_header(std::path::Path::new("secrets.toml"), secrets_data.clone().into_inner().len() as u64), secrets_data)?;
-
-let mut pass = loop {
-    let pass_prompt = prompt_password(" !!! Please input the database password: ")?;
-    let verification_prompt = prompt_password(" !!! Please verify the database password: ")?;
-    if pass_prompt != verification_prompt {
-        println!("Verification failed, try again"); 
-        drop(pass_prompt);
-        drop(verification_prompt);
-        continue;
-    } else {
-        break Secret::new(pass_prompt);
-    }
-};
+let pass = prompt_new_pass()?;
 
 // ask the user about the prefered db location
 let new_db_path = loop {
     let path_prompter = PathBuf::from(Text::new("Please specify an empty path to put the db in (\".tar.rotp\" will be appended to it):".into()).prompt()?);
-    if !path_prompter.exists() {
-        match std::fs::File::create(format!("{}.tar.rotp",&path_prompter.display())) {
-            std::result::Result::Ok(_) => {
-                // File created successfully, break the loop and return the path
-                break path_prompter;
-            },
-            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
-                println!("Permission denied for path: {:?}", path_prompter);
-                // Handle the error, possibly by continuing the loop to prompt again
-                continue;
-            },
-            Err(e) => {
-                // Handle other kinds of errors
-                println!("An error occurred: {:?}", e);
-                // Continue the loop to prompt again
-                continue;
-            },
-        }
-    } else {
+    let db_path = PathBuf::from(format!("{}.tar.rotp", path_prompter.display()));
+    if path_prompter.exists() || db_path.exists() {
         println!("Path already contains something, specify an empty one");
-        // Continue the loop to prompt again
         continue;
     }
+    match DB::create(db_path, pass.clone()) {
+        std::result::Result::Ok(_) => break path_prompter,
+        Err(e) => {
+            println!("Could not create the database there: {}", e);
+            // Continue the loop to prompt again
+            continue;
+        }
+    }
 };
 println!("{}", new_db_path.display());
 
-let mut new_db_file_handle = std::fs::File::create(format!("{}.tar.rotp",&new_db_path.display()))?;
-new_db_file_handle.write(&DB::encrypt(pass, builder.into_inner()?)?)?;
-new_db_file_handle.flush()?;
 //building done at this point
-//setting the env var 
-let mut env_profile = std::fs::OpenOptions::new().append(true).open(format!("{}/.profile", std::env::var("HOME")?))?;
-remove_older_profile_exports(&format!("{}/.profile", std::env::var("HOME")?))?;
-env_profile.write_all(format!("\nexport ROTP_DB={}{}",&new_db_path.display(), ".tar.rotp").as_bytes())?;
-//set the current session to the new update profile
+//make it the default vault
+let mut config = Config::load()?;
+config.default = Some(format!("{}.tar.rotp", new_db_path.display()));
+config.save()?;
 println!("Done!!!");
-println!("Please run this command immediatly \n source ~/.profile");
+println!("It is now the default database, set in {}", Config::path()?.display());
 Ok((true))
 }
 
@@ -229,54 +1060,348 @@ fn create_tar_header<S: std::convert::AsRef<std::path::Path>>(name: S, size: u64
     header.set_cksum();
     header
 }
-//this will remove the older export env var entries from the ~/.profile file
-fn remove_older_profile_exports<P>(profile: &P) -> Result<()> 
-where
-    P: AsRef<std::path::Path>,
-{
-    let mut file = File::open(profile)?;
-    // Open the file for reading
-    let mut buffer = Vec::new();
-
-    // Read the whole file into the buffer
-    file.read_to_end(&mut buffer)?;
-
-    // Convert the buffer into a string
-    let content = String::from_utf8_lossy(&buffer);
-
-    // Split the content into lines, filter out the target line, and join them back
-    let modified_content = content.lines()
-        .filter(|line| !line.contains("export ROTP_DB"))
-        .collect::<Vec<&str>>()
-        .join("\n");
-    // Open the file for writing
-        let mut file = File::create(profile)?;
-
-        // Write the modified content back to the file
-        file.write_all(modified_content.as_bytes())?;
-
-    Ok(())
-}
-
-
 #[cfg(test)]
 mod tests {
     use std::io::{Read, Seek, SeekFrom, Write};
 
     use age::secrecy::Secret;
-    use super::{onboarding, DB};
-    use tempfile::tempfile;
+    use super::{backup_path, Key, StorageError, DB};
+    use crate::types::{Edit, OTP};
+    use tempfile::{tempdir, tempfile};
     #[test]
     fn test_encryption_decryption() {
         let mut data = tempfile().unwrap();
         let encrypted = DB::encrypt(Secret::new("test".to_string()), "shimmi shiimmi ya u lalala".as_bytes().to_vec()).unwrap();
         data.write_all(&encrypted);
         data.seek(SeekFrom::Start(0)).unwrap();
-        let decrypted =  DB::decrypt(Secret::new("test".to_string()), std::io::BufReader::new(data)).unwrap();
+        let decrypted =  DB::decrypt(&Key::Passphrase(Secret::new("test".to_string())), std::io::BufReader::new(data)).unwrap();
         assert_eq!("shimmi shiimmi ya u lalala".as_bytes().to_vec(), decrypted);
     }
     #[test]
     fn test_onboarding() {
        // onboarding();
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_save_and_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let mut db = DB::create(path.clone(), pass.clone()).unwrap();
+        assert!(db.entries().is_empty());
+
+        let uri = "otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        db.add(OTP::parse_uri(uri).unwrap());
+        db.add(OTP::parse_uri("otpauth://hotp/ACME:jane?secret=JBSWY3DPEHPK3PXP&counter=1").unwrap());
+        assert!(db.remove(1).is_some());
+        assert!(db.remove(1).is_none());
+        db.save().unwrap();
+
+        let reopened = DB::open(path.clone(), Key::Passphrase(pass)).unwrap();
+        let uris: Vec<String> = reopened.entries().iter().map(|otp| otp.to_uri()).collect();
+        assert_eq!(uris, vec![uri.to_string()]);
+
+        assert!(DB::open(path, Key::Passphrase(Secret::new("wrong".to_string()))).is_err());
+    }
+
+    #[test]
+    fn test_backups() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let mut db = DB::create(path.clone(), pass.clone()).unwrap();
+        db.set_backups(2);
+        assert!(DB::list_backups(&path).unwrap().is_empty());
+
+        let uris = [
+            "otpauth://totp/ACME:one?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/ACME:two?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/ACME:three?secret=JBSWY3DPEHPK3PXP",
+        ];
+        for uri in uris {
+            db.add(OTP::parse_uri(uri).unwrap());
+            db.save().unwrap();
+        }
+        // only two generations are kept: with two and with one entry
+        let backups = DB::list_backups(&path).unwrap();
+        assert_eq!(backups.iter().map(|b| b.generation).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(!backup_path(&path, 3).exists());
+        // no temporary files are left behind, only the lock file
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 4);
+
+        assert!(DB::restore_backup(&path, 1, Key::Passphrase(Secret::new("wrong".to_string())), 2).is_err());
+        assert!(DB::restore_backup(&path, 3, Key::Passphrase(pass.clone()), 2).is_err());
+        DB::restore_backup(&path, 2, Key::Passphrase(pass.clone()), 2).unwrap();
+        assert_eq!(DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap().entries().len(), 1);
+        // the database that was replaced is the newest backup now
+        DB::restore_backup(&path, 1, Key::Passphrase(pass.clone()), 2).unwrap();
+        assert_eq!(DB::open(path, Key::Passphrase(pass)).unwrap().entries().len(), 3);
+    }
+
+    #[test]
+    fn test_concurrent_saves() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let mut setup = DB::create(path.clone(), pass.clone()).unwrap();
+        let shared = "otpauth://totp/ACME:shared?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        let gone = "otpauth://totp/ACME:gone?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        setup.add(OTP::parse_uri(shared).unwrap());
+        setup.add(OTP::parse_uri(gone).unwrap());
+        setup.save().unwrap();
+        assert!(DB::create(path.clone(), pass.clone()).is_err());
+
+        let mut first = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        let mut second = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        let from_first = "otpauth://totp/ACME:first?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        let from_second = "otpauth://totp/ACME:second?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        first.add(OTP::parse_uri(from_first).unwrap());
+        first.save().unwrap();
+
+        second.add(OTP::parse_uri(from_second).unwrap());
+        second.remove(1);
+        second.entries_mut()[0].edit(Edit { account: Some("renamed".to_string()), ..Edit::default() }).unwrap();
+        let err = second.save().unwrap_err();
+        assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::ChangedOnDisk { .. })));
+
+        second.reload_and_merge().unwrap();
+        second.save().unwrap();
+        let uris: Vec<String> = DB::open(path, Key::Passphrase(pass)).unwrap().entries().iter().map(|otp| otp.to_uri()).collect();
+        assert_eq!(uris, vec![shared.replace("shared", "renamed").as_str(), from_first, from_second]);
+    }
+
+    #[test]
+    fn test_conflicting_saves() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let mut setup = DB::create(path.clone(), pass.clone()).unwrap();
+        let uri = "otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&counter=0";
+        setup.add(OTP::parse_uri(uri).unwrap());
+        setup.save().unwrap();
+
+        let mut first = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        let mut second = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        first.entries_mut()[0].edit(Edit { counter: Some(5), ..Edit::default() }).unwrap();
+        first.save().unwrap();
+        second.entries_mut()[0].increment_counter();
+        assert!(second.save().is_err());
+        second.reload_and_merge().unwrap();
+        assert_eq!(second.entries()[0].counter(), Some(5));
+        second.save().unwrap();
+
+        let mut first = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        first.entries_mut()[0].edit(Edit { account: Some("jane".to_string()), ..Edit::default() }).unwrap();
+        first.save().unwrap();
+        second.entries_mut()[0].edit(Edit { account: Some("jim".to_string()), ..Edit::default() }).unwrap();
+        assert!(second.save().is_err());
+        let err = second.reload_and_merge().unwrap_err();
+        assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::Conflict { .. })));
+        assert_eq!(second.entries()[0].account(), "jim");
+        assert_eq!(DB::open(path, Key::Passphrase(pass)).unwrap().entries()[0].account(), "jane");
+    }
+
+    #[test]
+    fn test_use_counter() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let mut setup = DB::create(path.clone(), pass.clone()).unwrap();
+        let uri = "otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&counter=0";
+        setup.add(OTP::parse_uri(uri).unwrap());
+        setup.save().unwrap();
+        let id = setup.entries()[0].id().to_string();
+
+        let mut first = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        let mut second = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        assert_eq!(first.use_counter(&id, |otp| Ok(otp.counter())).unwrap(), Some(0));
+        assert_eq!(second.use_counter(&id, |otp| Ok(otp.counter())).unwrap(), Some(1));
+        assert_eq!(first.use_counter(&id, |otp| Ok(otp.counter())).unwrap(), Some(2));
+        assert_eq!(DB::open(path, Key::Passphrase(pass)).unwrap().entries()[0].counter(), Some(3));
+    }
+
+    #[test]
+    fn test_rekey() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let old = Secret::new("old".to_string());
+        let new = Secret::new("new".to_string());
+        let mut db = DB::create(path.clone(), old.clone()).unwrap();
+        let uri = "otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30";
+        db.add(OTP::parse_uri(uri).unwrap());
+        db.save().unwrap();
+
+        assert!(DB::rekey(&path, new.clone(), old.clone(), 1).is_err());
+        DB::rekey(&path, old.clone(), new.clone(), 1).unwrap();
+        assert!(DB::open(path.clone(), Key::Passphrase(old)).is_err());
+        let db = DB::open(path, Key::Passphrase(new)).unwrap();
+        assert_eq!(db.entries()[0].to_uri(), uri);
+    }
+
+    #[test]
+    fn test_recipients() {
+        use age::secrecy::ExposeSecret;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let identity = age::x25519::Identity::generate();
+        let identity_file = dir.path().join("key.txt");
+        std::fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+        let other_file = dir.path().join("other.txt");
+        std::fs::write(&other_file, age::x25519::Identity::generate().to_string().expose_secret()).unwrap();
+        let recipients = vec![identity.to_public().to_string()];
+
+        let mut db = DB::create(path.clone(), pass.clone()).unwrap();
+        db.add(OTP::parse_uri("otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30").unwrap());
+        assert!(db.encrypt_to_recipients(vec!["age1nope".to_string()], vec![identity_file.clone()]).is_err());
+        assert!(db.encrypt_to_recipients(recipients.clone(), vec![other_file.clone()]).is_err());
+        db.encrypt_to_recipients(recipients.clone(), vec![identity_file.clone()]).unwrap();
+        db.save().unwrap();
+
+        assert!(!DB::needs_passphrase(&path).unwrap());
+        let err = DB::open(path.clone(), Key::Passphrase(pass.clone())).err().unwrap();
+        assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::WrongKey { .. })));
+        assert!(DB::open(path.clone(), Key::Identities(vec![other_file])).is_err());
+        let mut db = DB::open(path.clone(), Key::Identities(vec![identity_file])).unwrap();
+        assert_eq!(db.recipients().unwrap(), recipients);
+        assert_eq!(db.entries().len(), 1);
+
+        db.encrypt_to_passphrase(pass.clone());
+        db.save().unwrap();
+        assert!(DB::needs_passphrase(&path).unwrap());
+        let db = DB::open(path, Key::Passphrase(pass)).unwrap();
+        assert!(db.recipients().unwrap().is_empty());
+        assert_eq!(db.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_team() {
+        use age::secrecy::ExposeSecret;
+        use super::TeamMember;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let mut keys = vec![];
+        for name in ["alice", "bob"] {
+            let identity = age::x25519::Identity::generate();
+            let file = dir.path().join(name);
+            std::fs::write(&file, identity.to_string().expose_secret()).unwrap();
+            keys.push((file, TeamMember { name: name.to_string(), recipient: identity.to_public().to_string() }));
+        }
+        let (alice, bob) = (vec![keys[0].0.clone()], vec![keys[1].0.clone()]);
+
+        let mut db = DB::create(path.clone(), Secret::new("test".to_string())).unwrap();
+        db.set_team(vec![keys[0].1.clone()], alice.clone()).unwrap();
+        db.save().unwrap();
+        assert!(DB::open(path.clone(), Key::Identities(bob.clone())).is_err());
+
+        let mut db = DB::open(path.clone(), Key::Identities(alice.clone())).unwrap();
+        db.add_member(keys[1].1.clone(), alice.clone()).unwrap();
+        assert!(db.add_member(keys[1].1.clone(), alice.clone()).is_err());
+        db.save().unwrap();
+        let mut db = DB::open(path.clone(), Key::Identities(bob.clone())).unwrap();
+        assert_eq!(db.team().unwrap(), vec![keys[0].1.clone(), keys[1].1.clone()]);
+
+        // bob can't remove himself, that would lock him out
+        assert!(db.remove_member("bob", bob.clone()).is_err());
+        assert!(db.remove_member("carol", bob.clone()).is_err());
+        assert_eq!(db.remove_member("alice", bob.clone()).unwrap(), keys[0].1);
+        db.save().unwrap();
+        assert!(DB::open(path.clone(), Key::Identities(alice)).is_err());
+        assert_eq!(DB::open(path, Key::Identities(bob)).unwrap().team().unwrap(), vec![keys[1].1.clone()]);
+    }
+
+    #[test]
+    fn test_format_versions() {
+        use super::{build_archive, Meta};
+        use crate::migrate::FORMAT_VERSION;
+
+        let dir = tempdir().unwrap();
+        let pass = Key::Passphrase(Secret::new("rotp".to_string()));
+        let path = dir.path().join("v0.tar.rotp");
+        std::fs::copy("testing/vaults/v0.tar.rotp", &path).unwrap();
+        let mut db = DB::open(path.clone(), pass.clone()).unwrap();
+        assert_eq!(db.upgraded_from(), Some(0));
+        assert_eq!(db.entries()[0].to_string(), "ACME (john)");
+        assert_eq!(db.entries()[0].id().len(), 16);
+        db.save().unwrap();
+
+        let db = DB::open(path, pass.clone()).unwrap();
+        assert_eq!(db.upgraded_from(), None);
+        assert_eq!(db.meta().format_version, FORMAT_VERSION);
+        assert_eq!(db.entries().len(), 1);
+
+        let mut meta = Meta::new();
+        meta.format_version = FORMAT_VERSION + 1;
+        let newer = dir.path().join("newer.tar.rotp");
+        let archive = build_archive(&meta, "[secrets]", &[]).unwrap();
+        std::fs::write(&newer, DB::encrypt(Secret::new("rotp".to_string()), archive).unwrap()).unwrap();
+        assert!(DB::open(newer, pass).is_err());
+    }
+
+    #[test]
+    fn test_images() {
+        use super::ImageKind;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.tar.rotp");
+        let pass = Secret::new("test".to_string());
+        let qr = std::fs::read("testing/canvas.png").unwrap();
+        let mut db = DB::create(path.clone(), pass.clone()).unwrap();
+        db.add(OTP::parse_uri("otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME").unwrap());
+        db.add(OTP::parse_uri("otpauth://totp/ACME:jane?secret=JBSWY3DPEHPK3PXP&issuer=ACME").unwrap());
+        let (john, jane) = (db.entries()[0].id().to_string(), db.entries()[1].id().to_string());
+
+        assert!(db.set_image("nope", ImageKind::Qr, qr.clone()).is_err());
+        assert!(db.set_image(&john, ImageKind::Icon, b"not an image".to_vec()).is_err());
+        db.set_image(&john, ImageKind::Qr, qr.clone()).unwrap();
+        db.set_image(&john, ImageKind::Qr, qr.clone()).unwrap();
+        db.set_image(&jane, ImageKind::Icon, qr.clone()).unwrap();
+        assert!(db.image(&john, ImageKind::Icon).is_none());
+        db.save().unwrap();
+
+        let mut db = DB::open(path.clone(), Key::Passphrase(pass.clone())).unwrap();
+        assert_eq!(db.find(&john).unwrap().account(), "john");
+        assert_eq!(db.image(&john, ImageKind::Qr).unwrap(), qr.as_slice());
+        assert_eq!(db.image(&jane, ImageKind::Icon).unwrap(), qr.as_slice());
+        assert!(db.remove_image(&jane, ImageKind::Icon));
+        assert!(!db.remove_image(&jane, ImageKind::Icon));
+
+        db.remove(0);
+        assert_eq!(db.gc_images(), 1);
+        db.save().unwrap();
+        let db = DB::open(path, Key::Passphrase(pass)).unwrap();
+        assert!(db.image(&john, ImageKind::Qr).is_none());
+        assert_eq!(db.entries()[0].id(), jane);
+    }
+
+    #[test]
+    fn test_passphrase_sources() {
+        use std::os::unix::io::AsRawFd;
+        use age::secrecy::ExposeSecret;
+        use super::PassSource;
+
+        let file = std::path::PathBuf::from("/secret");
+        assert_eq!(PassSource::pick(Some(3), Some(file.clone()), None, true, Some("cmd")), PassSource::Fd(3));
+        assert_eq!(PassSource::pick(None, Some(file.clone()), None, true, None), PassSource::File(file));
+        assert_eq!(PassSource::pick(None, None, None, true, Some("cmd")), PassSource::Env);
+        assert_eq!(PassSource::pick(None, None, None, false, Some("cmd")), PassSource::Command("cmd".to_string()));
+        assert_eq!(PassSource::pick(None, None, None, false, None), PassSource::Prompt);
+
+        let dir = tempdir().unwrap();
+        let db = "test.tar.rotp".to_string();
+        let path = dir.path().join("pass");
+        std::fs::write(&path, "from file\nignored\n").unwrap();
+        assert_eq!(PassSource::File(path.clone()).read(&db).unwrap().expose_secret(), "from file");
+        let opened = std::fs::File::open(&path).unwrap();
+        assert_eq!(PassSource::Fd(opened.as_raw_fd()).read(&db).unwrap().expose_secret(), "from file");
+
+        let command = PassSource::Command("printf 'from command\\r\\n'".to_string());
+        assert_eq!(command.read(&db).unwrap().expose_secret(), "from command");
+        for failing in ["exit 1", "true"] {
+            let err = PassSource::Command(failing.to_string()).read(&db).unwrap_err();
+            assert!(matches!(err.downcast_ref::<StorageError>(), Some(StorageError::NoPassphrase { .. })));
+        }
+    }
+}
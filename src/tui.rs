@@ -0,0 +1,326 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use arboard::Clipboard;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style, Stylize},
+    widgets::{Block, Cell, Paragraph, Row, Table, TableState, Tabs},
+    DefaultTerminal, Frame,
+};
+
+use crate::clock::Clock;
+use crate::otp;
+use crate::output::current_code;
+use crate::search::search;
+use crate::storage::DB;
+use crate::types::OTP;
+
+/// Width in cells of the countdown bars.
+const BAR_WIDTH: usize = 10;
+
+/// Seconds left under which a countdown turns red.
+const EXPIRING: u64 = 5;
+
+const HELP: &str = "↑/↓ move  / search  enter copy  q quit";
+
+const VAULTS_HELP: &str = "↑/↓ move  tab next vault  / search  enter copy  q quit";
+
+/// Code column of counter based entries until their code is revealed.
+const HIDDEN: &str = "enter";
+
+/// Shows the entries of `vaults` full screen until the user quits, one tab
+/// per vault named by the string next to it. Codes and countdowns are
+/// redrawn every second, typing after `/` narrows the lists down to fuzzy
+/// matches and enter copies the code of the selected entry to the clipboard.
+/// Codes of counter based entries are only shown once enter used them up.
+pub fn run(vaults: Vec<(String, DB)>, clock: &dyn Clock) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = App::new(vaults).run(&mut terminal, clock);
+    ratatui::restore();
+    result
+}
+
+/// An opened vault and what of it is shown.
+struct Vault {
+    name: String,
+    db: DB,
+    /// Indices into the entries of `db` of those shown, best match first.
+    visible: Vec<usize>,
+    table: TableState,
+    /// Id of the counter based entry whose code was last revealed, and that
+    /// code.
+    revealed: Option<(String, String)>,
+}
+
+impl Vault {
+    /// Recomputes the entries shown after the filter or the entries changed,
+    /// selecting the best match.
+    fn refilter(&mut self, filter: &str) {
+        self.visible = search(self.db.entries(), filter).into_iter().map(|(index, _)| index).collect();
+        self.table.select(moved(Some(0), self.visible.len(), KeyCode::Null));
+    }
+
+}
+
+struct App {
+    vaults: Vec<Vault>,
+    /// Index into `vaults` of the one shown.
+    current: usize,
+    filter: String,
+    /// Whether keys go to the filter.
+    typing: bool,
+    status: Option<String>,
+    /// Opened on the first copy. On X11 and Wayland the copied code stays
+    /// available only as long as it is open, so it is kept until exit.
+    clipboard: Option<Clipboard>,
+}
+
+impl App {
+    fn new(vaults: Vec<(String, DB)>) -> App {
+        let vaults = vaults
+            .into_iter()
+            .map(|(name, db)| Vault { name, db, visible: vec![], table: TableState::default(), revealed: None })
+            .collect();
+        let mut app = App {
+            vaults,
+            current: 0,
+            filter: String::new(),
+            typing: false,
+            status: None,
+            clipboard: None,
+        };
+        app.refilter();
+        app
+    }
+
+    /// Applies the filter to every vault, so switching keeps it.
+    fn refilter(&mut self) {
+        for vault in &mut self.vaults {
+            vault.refilter(&self.filter);
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal, clock: &dyn Clock) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame, clock))?;
+            if !event::poll(until_next_second())? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !self.handle(key, clock) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Reacts to a key press, false when the user asked to quit.
+    fn handle(&mut self, key: KeyEvent, clock: &dyn Clock) -> bool {
+        self.status = None;
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Enter => self.copy(clock),
+            KeyCode::Tab => self.current = (self.current + 1) % self.vaults.len(),
+            KeyCode::BackTab => self.current = (self.current + self.vaults.len() - 1) % self.vaults.len(),
+            KeyCode::Esc if self.typing || !self.filter.is_empty() => {
+                self.typing = false;
+                self.filter.clear();
+                self.refilter();
+            }
+            KeyCode::Backspace if self.typing => {
+                self.filter.pop();
+                self.refilter();
+            }
+            KeyCode::Char(c) if self.typing => {
+                self.filter.push(c);
+                self.refilter();
+            }
+            KeyCode::Char('/') => self.typing = true,
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            code => {
+                let vault = &mut self.vaults[self.current];
+                let selected = moved(vault.table.selected(), vault.visible.len(), code);
+                vault.table.select(selected);
+            }
+        }
+        true
+    }
+
+    /// Copies the code of the selected entry. A counter based entry is moved
+    /// past the code and saved before the code is shown and copied, so it is
+    /// never handed out twice.
+    fn copy(&mut self, clock: &dyn Clock) {
+        let vault = &mut self.vaults[self.current];
+        let index = match vault.table.selected() {
+            Some(row) => vault.visible[row],
+            None => return,
+        };
+        let otp = &vault.db.entries()[index];
+        let label = otp.to_string();
+        let id = otp.id().to_string();
+        let counter_based = otp.counter().is_some();
+        // counter based codes are made from the counter on disk, another
+        // ROTP process may have handed out codes since the vault was opened
+        let made = if counter_based {
+            vault.db.use_counter(&id, |otp| Ok(otp.code(clock)?))
+        } else {
+            otp.code(clock).map_err(Into::into)
+        };
+        let code = match made {
+            Ok(code) => code,
+            Err(err) if matches!(err.downcast_ref::<otp::Error>(), Some(otp::Error::ChallengeRequired)) => {
+                self.status = Some(format!("{label} answers challenges, use `rotp code --challenge`"));
+                return;
+            }
+            Err(err) => {
+                self.status = Some(format!("{label}: {err}"));
+                return;
+            }
+        };
+        if counter_based {
+            // the vault may have been reloaded
+            vault.refilter(&self.filter);
+            let row = vault.visible.iter().position(|&index| vault.db.entries()[index].id() == id);
+            vault.table.select(row);
+            vault.revealed = Some((id, code.clone()));
+        }
+        let copied = match &mut self.clipboard {
+            Some(clipboard) => clipboard.set_text(code),
+            None => Clipboard::new().and_then(|clipboard| self.clipboard.insert(clipboard).set_text(code)),
+        };
+        if let Err(err) = copied {
+            self.status = Some(format!("Could not copy the code: {err}"));
+            return;
+        }
+        self.status = Some(format!("Copied the code of {label}"));
+    }
+
+    fn draw(&mut self, frame: &mut Frame, clock: &dyn Clock) {
+        let tabs_height = if self.vaults.len() > 1 { 1 } else { 0 };
+        let [tabs, main, footer] =
+            Layout::vertical([Constraint::Length(tabs_height), Constraint::Min(1), Constraint::Length(1)])
+                .areas(frame.area());
+        let footer_text = match (&self.status, self.typing) {
+            (Some(status), _) => status.clone(),
+            (None, true) => format!("/{}▏", self.filter),
+            (None, false) if !self.filter.is_empty() => format!("/{}  esc clear", self.filter),
+            (None, false) if self.vaults.len() > 1 => VAULTS_HELP.to_string(),
+            (None, false) => HELP.to_string(),
+        };
+        frame.render_widget(Paragraph::new(footer_text).dim(), footer);
+        let names = self.vaults.iter().map(|vault| vault.name.clone());
+        frame.render_widget(Tabs::new(names).select(self.current).highlight_style(Modifier::REVERSED), tabs);
+
+        let vault = &mut self.vaults[self.current];
+        let block = Block::bordered().title(format!(" ROTP: {} ", vault.db.path().display()));
+        if vault.db.entries().is_empty() {
+            frame.render_widget(Paragraph::new("No entries yet, add one with `rotp add`").block(block), main);
+            return;
+        }
+        if vault.visible.is_empty() {
+            frame.render_widget(Paragraph::new(format!("No entries match {}", self.filter)).block(block), main);
+            return;
+        }
+        let rows = vault.visible.iter().map(|index| {
+            let otp = &vault.db.entries()[*index];
+            Row::new(vec![
+                Cell::from(otp.issuer()),
+                Cell::from(otp.account()),
+                Cell::from(otp.tags().join(", ")).dim(),
+                Cell::from(code_text(otp, vault.revealed.as_ref(), clock)).bold(),
+                countdown_cell(otp, clock),
+            ])
+        });
+        let widths = [
+            Constraint::Fill(2),
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+            Constraint::Length(10),
+            Constraint::Length(BAR_WIDTH as u16 + 4),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["Issuer", "Account", "Tags", "Code", "Time"]).underlined())
+            .block(block)
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(table, main, &mut vault.table);
+    }
+}
+
+/// What the code column shows for `otp`: the current code of a time based
+/// entry, the code last revealed of a counter based one.
+fn code_text(otp: &OTP, revealed: Option<&(String, String)>, clock: &dyn Clock) -> String {
+    if let Some((_, code)) = revealed.filter(|(id, _)| id == otp.id()) {
+        return code.clone();
+    }
+    match current_code(otp, clock) {
+        Ok(Some(code)) => code,
+        Ok(None) if otp.kind() == "OCRA" => "challenge".to_string(),
+        Ok(None) => HIDDEN.to_string(),
+        Err(_) => "error".to_string(),
+    }
+}
+
+fn countdown_cell(otp: &OTP, clock: &dyn Clock) -> Cell<'static> {
+    let (remaining, period) = match (otp.seconds_remaining(clock), otp.period()) {
+        (Ok(Some(remaining)), Some(period)) => (remaining, period),
+        _ => return Cell::from(""),
+    };
+    let color = if remaining <= EXPIRING { Color::Red } else { Color::Green };
+    Cell::from(countdown(remaining, period, BAR_WIDTH)).fg(color)
+}
+
+/// A bar `width` cells wide that empties as the code ages, followed by the
+/// seconds left.
+fn countdown(remaining: u64, period: u64, width: usize) -> String {
+    let filled = ((remaining * width as u64).div_ceil(period) as usize).min(width);
+    format!("{}{} {:>2}s", "█".repeat(filled), "░".repeat(width - filled), remaining)
+}
+
+/// Selection after pressing `key` in a list of `len` entries, staying within
+/// the list.
+fn moved(selected: Option<usize>, len: usize, key: KeyCode) -> Option<usize> {
+    let last = len.checked_sub(1)?;
+    let current = selected.unwrap_or(0).min(last);
+    Some(match key {
+        KeyCode::Down | KeyCode::Char('j') => (current + 1).min(last),
+        KeyCode::Up | KeyCode::Char('k') => current.saturating_sub(1),
+        KeyCode::Home | KeyCode::Char('g') => 0,
+        KeyCode::End | KeyCode::Char('G') => last,
+        _ => current,
+    })
+}
+
+/// How long to wait for a key before codes and countdowns need redrawing.
+fn until_next_second() -> Duration {
+    let elapsed = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    Duration::from_secs(1) - Duration::from_nanos(elapsed.subsec_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::crossterm::event::KeyCode;
+
+    use super::{countdown, moved};
+
+    #[test]
+    fn countdowns() {
+        assert_eq!(countdown(30, 30, 10), "██████████ 30s");
+        assert_eq!(countdown(15, 30, 10), "█████░░░░░ 15s");
+        assert_eq!(countdown(1, 30, 10), "█░░░░░░░░░  1s");
+        assert_eq!(countdown(60, 60, 4), "████ 60s");
+    }
+
+    #[test]
+    fn navigation() {
+        assert_eq!(moved(Some(0), 3, KeyCode::Down), Some(1));
+        assert_eq!(moved(Some(2), 3, KeyCode::Char('j')), Some(2));
+        assert_eq!(moved(Some(0), 3, KeyCode::Up), Some(0));
+        assert_eq!(moved(Some(1), 3, KeyCode::Char('G')), Some(2));
+        assert_eq!(moved(Some(2), 3, KeyCode::Home), Some(0));
+        assert_eq!(moved(Some(5), 3, KeyCode::Null), Some(2));
+        assert_eq!(moved(None, 0, KeyCode::Down), None);
+    }
+}
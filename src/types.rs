@@ -1,13 +1,26 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snafu::prelude::*;
+use std::fmt;
 use url::Url;
-use bardecoder::default_builder;
-use image::io::Reader;
-use image::{DynamicImage, GenericImageView};
-use anyhow::{Ok, Result};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use image::DynamicImage;
+use data_encoding::HEXLOWER;
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::clock::{self, Clock};
+use crate::ocra::{self, make_ocra, Inputs, Suite};
+use crate::otp::{
+    self, hex_to_secret, make_hotp, make_steam_totp, make_totp, normalize_secret, verify_hotp, verify_totp, Window,
+};
+
+/// Characters left as they are when percent-encoding labels and parameters.
+const URI_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 #[derive(Debug, Snafu)]
-enum OtpError {
+pub enum OtpError {
     #[snafu(display("URI didn't contain a label: {err}"))]
     LabelNotProvided { err: String },
     #[snafu(display("HOTP URI didn't contain a counter: {err}"))]
@@ -20,177 +33,765 @@ enum OtpError {
     CannotIdentifyOtpType { err: String },
     #[snafu(display("QR code could not be parsed, try a simpler one with no images."))]
     CannotParseQR,
+    #[snafu(display("URI secret is not valid base32: {err}"))]
+    InvalidSecret { err: String },
+    #[snafu(display("URI algorithm is not supported (SHA1, SHA256, SHA512): {err}"))]
+    UnknownAlgorithm { err: String },
+    #[snafu(display("URI digits must be a number between 1 and 10: {err}"))]
+    InvalidDigits { err: String },
+    #[snafu(display("TOTP URI period must be a positive number of seconds: {err}"))]
+    InvalidPeriod { err: String },
+    #[snafu(display("HOTP URI counter is not a valid number: {err}"))]
+    InvalidCounter { err: String },
+    #[snafu(display("OCRA URI suite is not valid: {err}"))]
+    InvalidSuite { err: String },
+    #[snafu(display("Cannot change the {err}"))]
+    NotEditable { err: String },
 }
-#[derive(Debug, Serialize)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum OTP {
     HOTP(HOTP),
-    TOTP(TOTP)
+    TOTP(TOTP),
+    Steam(TOTP),
+    OCRA(OCRA),
 }
 
+impl OTP {
+    /// What `parse_uri` accepted in the label of `uri` but looks wrong, for
+    /// the caller to show.
+    pub fn uri_warning(uri: &str) -> Option<String> {
+        let url = Url::parse(uri).ok()?;
+        Label::parse(raw_label(&url), query_param(&url, "issuer")).ok()?.warning
+    }
+
+    pub fn parse_uri(uri: &str) -> Result<OTP, OtpError> {
+        let url = Url::parse(uri).map_err(|e| OtpError::NotOtpLink { err: e.to_string() })?;
+        if url.scheme() != "otpauth" {
+            return Err(OtpError::NotOtpLink {
+                err: format!("unexpected scheme {}", url.scheme()),
+            });
+        }
 
+        let label = Label::parse(raw_label(&url), query_param(&url, "issuer"))?;
 
-    impl OTP{
-        pub fn parse_uri(uri: &String) -> Result<(OTP)> {
-            let url = Url::parse(uri).expect("Failed to parse URL");
-        
-            if uri.contains("otpauth://") {
-                let mut path_segments = url.path_segments().expect("Failed to get path segments");
-        
-                // The label is the first path segment after "totp/"
-                let label = path_segments.next().expect("Failed to get label");
-        
-                if uri.contains("totp") {
-                    let mut totp = TOTP::new(&uri, &url, label.to_string(), "/wip".to_string()).unwrap();
-                    return Ok(OTP::TOTP(totp))
+        let otp = match url.host_str() {
+            Some("totp") => {
+                let totp = TOTP::new(uri, &url, label, new_id())?;
+                if is_steam(&url, &totp.issuer) {
+                    OTP::Steam(totp)
                 } else {
-                    let mut hotp =
-                        HOTP::new(&uri, &url, label.to_string(), "/wip".to_string()).unwrap();
-                    return Ok(OTP::HOTP(hotp))
+                    OTP::TOTP(totp)
+                }
+            }
+            Some("hotp") => OTP::HOTP(HOTP::new(uri, &url, label, new_id())?),
+            Some("ocra") => OTP::OCRA(OCRA::new(uri, &url, label, new_id())?),
+            other => {
+                return Err(OtpError::CannotIdentifyOtpType {
+                    err: other.unwrap_or_default().to_string(),
+                })
+            }
+        };
+        Ok(otp)
+    }
+
+    /// Builds a TOTP entry from values typed in by hand, using the Key URI
+    /// Format defaults (SHA1, 6 digits, 30 second period).
+    pub fn from_manual_entry(
+        issuer: &str,
+        account: &str,
+        secret: &str,
+        encoding: SecretEncoding,
+    ) -> Result<OTP, OtpError> {
+        if account.trim().is_empty() {
+            return Err(OtpError::LabelNotProvided { err: "no account name given".to_string() });
+        }
+        let mut totp = TOTP {
+            secret: canonical_secret(secret, encoding)?,
+            issuer: issuer.trim().to_string(),
+            account: account.trim().to_string(),
+            algo: Algo::SHA1,
+            digits: 6,
+            step: 30,
+            full_uri: String::new(),
+            id: new_id(),
+            tags: vec![],
+            notes: String::new(),
+        };
+        totp.full_uri = totp.to_uri();
+        Ok(OTP::TOTP(totp))
+    }
+
+    /// Identifier of the entry, unique within a database and kept across
+    /// edits.
+    pub fn id(&self) -> &str {
+        match self {
+            OTP::HOTP(hotp) => &hotp.id,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.id,
+            OTP::OCRA(ocra) => &ocra.id,
+        }
+    }
+
+    /// Words the entry was tagged with, see `Edit::tags`.
+    pub fn tags(&self) -> &[String] {
+        match self {
+            OTP::HOTP(hotp) => &hotp.tags,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.tags,
+            OTP::OCRA(ocra) => &ocra.tags,
+        }
+    }
+
+    /// Free text kept with the entry, empty when there is none.
+    pub fn notes(&self) -> &str {
+        match self {
+            OTP::HOTP(hotp) => &hotp.notes,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.notes,
+            OTP::OCRA(ocra) => &ocra.notes,
+        }
+    }
+
+    /// Who issued the entry, empty when the URI named no issuer.
+    pub fn issuer(&self) -> &str {
+        match self {
+            OTP::HOTP(hotp) => &hotp.issuer,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.issuer,
+            OTP::OCRA(ocra) => &ocra.issuer,
+        }
+    }
+
+    /// The account the entry belongs to, e.g. an email address.
+    pub fn account(&self) -> &str {
+        match self {
+            OTP::HOTP(hotp) => &hotp.account,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.account,
+            OTP::OCRA(ocra) => &ocra.account,
+        }
+    }
+
+    /// The shared secret in base32, as it appears in the entry's URI.
+    pub fn secret(&self) -> &str {
+        match self {
+            OTP::HOTP(hotp) => &hotp.secret,
+            OTP::TOTP(totp) | OTP::Steam(totp) => &totp.secret,
+            OTP::OCRA(ocra) => &ocra.secret,
+        }
+    }
+
+    /// Builds a canonical `otpauth://` URI from the current state of the
+    /// entry, so edited entries can be exported and imported again.
+    pub fn to_uri(&self) -> String {
+        match self {
+            OTP::HOTP(hotp) => hotp.to_uri(),
+            OTP::TOTP(totp) => totp.to_uri(),
+            OTP::Steam(totp) => format!("{}&encoder=steam", totp.to_uri()),
+            OTP::OCRA(ocra) => ocra.to_uri(),
+        }
+    }
+
+    /// Generates the current code for the entry with its own algorithm,
+    /// reading the time of TOTP entries from `clock`.
+    pub fn code(&self, clock: &dyn Clock) -> Result<String, otp::Error> {
+        match self {
+            OTP::HOTP(hotp) => make_hotp(&hotp.secret, hotp.counter, hotp.algo, hotp.digits),
+            OTP::TOTP(totp) => {
+                make_totp(&totp.secret, totp.step as u64, totp.algo, totp.digits, clock)
+            }
+            OTP::Steam(totp) => make_steam_totp(&totp.secret, totp.step as u64, clock),
+            OTP::OCRA(_) => Err(otp::Error::ChallengeRequired),
+        }
+    }
+
+    /// Answers an OCRA challenge with the entry, see `OCRA::answer`. None for
+    /// entries that aren't challenge-response.
+    pub fn answer(
+        &self,
+        question: &str,
+        password: Option<&str>,
+        session: Option<&[u8]>,
+        clock: &dyn Clock,
+    ) -> Option<Result<String, ocra::Error>> {
+        match self {
+            OTP::OCRA(ocra) => Some(ocra.answer(question, password, session, clock)),
+            _ => None,
+        }
+    }
+
+    /// Checks `code` against the entry, accepting the `window` periods around
+    /// the current one of TOTP entries or the `window` counters after the
+    /// current one of HOTP entries. An HOTP entry that matches moves past the
+    /// matching counter. None for entries that can't be verified this way.
+    pub fn verify(&mut self, code: &str, window: u64, clock: &dyn Clock) -> Option<Result<bool, otp::Error>> {
+        let verified = match self {
+            OTP::HOTP(hotp) => {
+                match verify_hotp(&hotp.secret, code, hotp.counter, window, hotp.algo, hotp.digits) {
+                    Ok(Some(counter)) => {
+                        hotp.counter = counter + 1;
+                        self.refresh_uri();
+                        Ok(true)
                     }
-            } else {
-                Err((OtpError::NotOtpLink{ err: "failed at parse_uri".to_string()}).into())
+                    matched => matched.map(|_| false),
+                }
+            }
+            OTP::TOTP(totp) => {
+                let window = Window { behind: window, ahead: window, last_used: None };
+                verify_totp(&totp.secret, code, totp.step as u64, window, totp.algo, totp.digits, clock)
+                    .map(|step| step.is_some())
+            }
+            OTP::Steam(_) | OTP::OCRA(_) => return None,
+        };
+        Some(verified)
+    }
+
+    /// Counter of a counter based entry, `None` for time based ones.
+    pub fn counter(&self) -> Option<u64> {
+        match self {
+            OTP::HOTP(hotp) => Some(hotp.counter),
+            OTP::OCRA(ocra) if Suite::parse(&ocra.suite).is_ok_and(|suite| suite.counter) => Some(ocra.counter),
+            _ => None,
+        }
+    }
+
+    /// Moves a counter based entry past the code that was just used, so it
+    /// isn't handed out again. Returns whether the entry has a counter.
+    pub fn increment_counter(&mut self) -> bool {
+        let counter = match self {
+            OTP::HOTP(hotp) => &mut hotp.counter,
+            OTP::OCRA(ocra) if Suite::parse(&ocra.suite).is_ok_and(|suite| suite.counter) => &mut ocra.counter,
+            _ => return false,
+        };
+        *counter += 1;
+        self.refresh_uri();
+        true
+    }
+
+    /// Name of the kind of entry, as written in the database.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OTP::HOTP(_) => "HOTP",
+            OTP::TOTP(_) => "TOTP",
+            OTP::Steam(_) => "Steam",
+            OTP::OCRA(_) => "OCRA",
+        }
+    }
+
+    /// Applies `edit` to the entry. Nothing is changed when one of the
+    /// changes doesn't apply to this kind of entry or is out of range.
+    pub fn edit(&mut self, edit: Edit) -> Result<(), OtpError> {
+        let not_editable = |what: &str| OtpError::NotEditable { err: format!("{} of {} entries", what, self.kind()) };
+        let (has_params, has_period, has_counter) = match self {
+            OTP::HOTP(_) => (true, false, true),
+            OTP::TOTP(_) => (true, true, false),
+            OTP::Steam(_) => (false, true, false),
+            OTP::OCRA(_) => (false, false, true),
+        };
+        if !has_params && edit.algo.is_some() {
+            return Err(not_editable("algorithm"));
+        }
+        if !has_params && edit.digits.is_some() {
+            return Err(not_editable("digits"));
+        }
+        if !has_period && edit.period.is_some() {
+            return Err(not_editable("period"));
+        }
+        if !has_counter && edit.counter.is_some() {
+            return Err(not_editable("counter"));
+        }
+        if let Some(digits) = edit.digits.filter(|digits| !(1..=10).contains(digits)) {
+            return Err(OtpError::InvalidDigits { err: digits.to_string() });
+        }
+        if edit.period == Some(0) {
+            return Err(OtpError::InvalidPeriod { err: "0".to_string() });
+        }
+        if edit.account.as_ref().is_some_and(|account| account.trim().is_empty()) {
+            return Err(OtpError::LabelNotProvided { err: "no account name given".to_string() });
+        }
+
+        let (issuer, account, tags, notes) = match self {
+            OTP::HOTP(hotp) => {
+                hotp.algo = edit.algo.unwrap_or(hotp.algo);
+                hotp.digits = edit.digits.unwrap_or(hotp.digits);
+                hotp.counter = edit.counter.unwrap_or(hotp.counter);
+                (&mut hotp.issuer, &mut hotp.account, &mut hotp.tags, &mut hotp.notes)
             }
+            OTP::TOTP(totp) | OTP::Steam(totp) => {
+                totp.algo = edit.algo.unwrap_or(totp.algo);
+                totp.digits = edit.digits.unwrap_or(totp.digits);
+                totp.step = edit.period.unwrap_or(totp.step);
+                (&mut totp.issuer, &mut totp.account, &mut totp.tags, &mut totp.notes)
+            }
+            OTP::OCRA(ocra) => {
+                ocra.counter = edit.counter.unwrap_or(ocra.counter);
+                (&mut ocra.issuer, &mut ocra.account, &mut ocra.tags, &mut ocra.notes)
+            }
+        };
+        if let Some(new) = edit.issuer {
+            *issuer = new.trim().to_string();
+        }
+        if let Some(new) = edit.account {
+            *account = new.trim().to_string();
         }
+        if let Some(new) = edit.tags {
+            *tags = new.iter().map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect();
+        }
+        if let Some(new) = edit.notes {
+            *notes = new.trim().to_string();
+        }
+        self.refresh_uri();
+        Ok(())
     }
 
+    /// Keeps the stored URI in line with the fields after a change.
+    fn refresh_uri(&mut self) {
+        let uri = self.to_uri();
+        match self {
+            OTP::HOTP(hotp) => hotp.full_uri = uri,
+            OTP::TOTP(totp) | OTP::Steam(totp) => totp.full_uri = uri,
+            OTP::OCRA(ocra) => ocra.full_uri = uri,
+        }
+    }
+
+    /// Seconds a code of a time based entry is valid for.
+    pub fn period(&self) -> Option<u64> {
+        match self {
+            OTP::HOTP(_) | OTP::OCRA(_) => None,
+            OTP::TOTP(totp) | OTP::Steam(totp) => Some(totp.step as u64),
+        }
+    }
+
+    /// Seconds until the code of a time based entry changes.
+    pub fn seconds_remaining(&self, clock: &dyn Clock) -> Result<Option<u64>, otp::Error> {
+        match self {
+            OTP::HOTP(_) | OTP::OCRA(_) => Ok(None),
+            OTP::TOTP(totp) | OTP::Steam(totp) => {
+                Ok(Some(clock::seconds_remaining(clock, totp.step as u64)?))
+            }
+        }
+    }
+}
 
+/// Changes to apply with `OTP::edit`, None leaves a field as it is.
+#[derive(Debug, Default)]
+pub struct Edit {
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+    pub algo: Option<Algo>,
+    pub digits: Option<u32>,
+    pub period: Option<u32>,
+    pub counter: Option<u64>,
+    /// Replaces all tags, blank ones are dropped.
+    pub tags: Option<Vec<String>>,
+    pub notes: Option<String>,
+}
 
+impl fmt::Display for OTP {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.issuer().is_empty() {
+            write!(f, "{}", self.account())
+        } else {
+            write!(f, "{} ({})", self.issuer(), self.account())
+        }
+    }
+}
 
-#[derive(Debug, Serialize)]
-enum Algo {
+/// The `issuer:account` label of a URI, split and percent-decoded.
+struct Label {
+    issuer: String,
+    account: String,
+    /// Set by `parse` when the label prefix and the `issuer` parameter
+    /// disagree.
+    warning: Option<String>,
+}
+
+/// Names the entry in errors, which must not show the URI with its secret.
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.issuer.is_empty() {
+            write!(f, "{}", self.account)
+        } else {
+            write!(f, "{}:{}", self.issuer, self.account)
+        }
+    }
+}
+
+impl Label {
+    /// Splits the raw label and reconciles its issuer prefix with the
+    /// `issuer` parameter, which wins when they disagree and leaves a
+    /// warning.
+    fn parse(raw: &str, issuer_param: Option<String>) -> Result<Label, OtpError> {
+        let label = percent_decode_str(raw).decode_utf8_lossy();
+        let (prefix, account) = match label.split_once(':') {
+            Some((prefix, account)) => (Some(prefix.trim()), account.trim()),
+            None => (None, label.trim()),
+        };
+        if account.is_empty() {
+            return Err(OtpError::LabelNotProvided { err: "no account name given".to_string() });
+        }
+        let mut warning = None;
+        let issuer = match (prefix, issuer_param) {
+            (Some(prefix), Some(param)) => {
+                if prefix != param {
+                    warning = Some(format!(
+                        "label issuer \"{}\" does not match issuer parameter \"{}\", using the parameter",
+                        prefix, param
+                    ));
+                }
+                param
+            }
+            (None, Some(param)) => param,
+            (Some(prefix), None) => prefix.to_string(),
+            (None, None) => String::new(),
+        };
+        Ok(Label {
+            issuer,
+            account: account.to_string(),
+            warning,
+        })
+    }
+
+    fn to_uri_label(&self) -> String {
+        if self.issuer.is_empty() {
+            encode_component(&self.account)
+        } else {
+            format!(
+                "{}:{}",
+                encode_component(&self.issuer),
+                encode_component(&self.account)
+            )
+        }
+    }
+}
+
+/// A new random entry identifier, 16 hex digits.
+pub fn new_id() -> String {
+    let mut bytes = [0u8; 8];
+    SystemRandom::new().fill(&mut bytes).expect("system randomness is unavailable");
+    HEXLOWER.encode(&bytes)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Algo {
     SHA1,
     SHA256,
     SHA512,
 }
-#[derive(Debug, Serialize)]
 
+impl Algo {
+    /// Name of the algorithm as used by the `algorithm` URI parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algo::SHA1 => "SHA1",
+            Algo::SHA256 => "SHA256",
+            Algo::SHA512 => "SHA512",
+        }
+    }
+}
+
+impl std::str::FromStr for Algo {
+    type Err = OtpError;
+
+    fn from_str(algo: &str) -> Result<Algo, OtpError> {
+        match algo {
+            "SHA1" => Ok(Algo::SHA1),
+            "SHA256" => Ok(Algo::SHA256),
+            "SHA512" => Ok(Algo::SHA512),
+            other => Err(OtpError::UnknownAlgorithm { err: other.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct HOTP {
     secret: String,
-    label: String,
     issuer: String,
+    account: String,
     algo: Algo,
+    digits: u32,
     counter: u64,
     full_uri: String,
-    img_path: String,
+    /// Stable identifier, names the entry's images inside the archive.
+    id: String,
+    /// Free-form words to find the entry by, e.g. `work`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
 }
 
 impl HOTP {
-    fn new(
-        uri: &String,
-        url: &Url,
-        label: String,
-        img_path: String,
-    ) -> Result<HOTP> {
-        let uri = uri.clone();
-        let url = url.clone();
-        let mut algo;
-        match url
-            .query_pairs()
-            .find(|(key, _)| key == "algorithm")
-            .map(|(_, value)| value.to_string())
-            .unwrap_or("SHA1".to_owned())
-            .as_str()
-        {
-            "SHA1" => algo = Algo::SHA1,
-            "SHA256" => algo = Algo::SHA256,
-            "SHA512" => algo = Algo::SHA512,
-            _ => algo = Algo::SHA1,
+    fn label(&self) -> Label {
+        Label {
+            issuer: self.issuer.clone(),
+            account: self.account.clone(),
+            warning: None,
         }
+    }
+
+    fn new(uri: &str, url: &Url, label: Label, id: String) -> Result<HOTP, OtpError> {
+        let counter = query_param(url, "counter")
+            .ok_or_else(|| OtpError::CounterNotProvided { err: label.to_string() })?;
         Ok(HOTP {
-            secret: url
-                .query_pairs()
-                .find(|(key, _)| key == "secret")
-                .map(|(_, value)| value.to_string())
-                .unwrap(),
-            label: label.clone(),
-            issuer: url
-                .query_pairs()
-                .find(|(key, _)| key == "issuer")
-                .map(|(_, value)| value.to_string())
-                .unwrap_or(label),
-            algo,
-            counter: url
-            .query_pairs()
-            .find(|(key, _)| key == "counter")
-            .map(|(_, value)| value.to_string())
-            .unwrap().parse::<u64>().unwrap(),
-            full_uri: uri,
-            img_path,
+            secret: parse_secret(url, &label)?,
+            issuer: label.issuer,
+            account: label.account,
+            algo: parse_algo(url)?,
+            digits: parse_digits(url)?,
+            counter: counter
+                .parse::<u64>()
+                .map_err(|_| OtpError::InvalidCounter { err: counter })?,
+            full_uri: uri.to_string(),
+            id,
+            tags: vec![],
+            notes: String::new(),
         })
     }
+
+    pub fn to_uri(&self) -> String {
+        format!(
+            "otpauth://hotp/{}?secret={}{}&algorithm={}&digits={}&counter={}",
+            self.label().to_uri_label(),
+            encode_component(&self.secret),
+            issuer_param(&self.issuer),
+            self.algo.as_str(),
+            self.digits,
+            self.counter
+        )
+    }
 }
-#[derive(Debug, Serialize)]
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TOTP {
     secret: String,
-    label: String,
     issuer: String,
+    account: String,
     algo: Algo,
+    digits: u32,
     step: u32,
     full_uri: String,
-    img_path: String,
+    /// Stable identifier, names the entry's images inside the archive.
+    id: String,
+    /// Free-form words to find the entry by, e.g. `work`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
 }
 
 impl TOTP {
-    fn new(uri: &String, url: &Url, label: String, img_path: String) -> Result<TOTP> {
-        let uri = uri.clone();
-        let url = url.clone();
-        let mut algo;
-        match url
-            .query_pairs()
-            .find(|(key, _)| key == "algorithm")
-            .map(|(_, value)| value.to_string())
-            .unwrap_or("SHA1".to_owned())
-            .as_str()
-        {
-            "SHA1" => algo = Algo::SHA1,
-            "SHA256" => algo = Algo::SHA256,
-            "SHA512" => algo = Algo::SHA512,
-            _ => algo = Algo::SHA1,
-        }
-    
-        let totp = TOTP {
-            secret: url
-                .query_pairs()
-                .find(|(key, _)| key == "secret")
-                .map(|(_, value)| value.to_string())
-                .unwrap(),
-            label: label.clone(),
-            issuer: url
-                .query_pairs()
-                .find(|(key, _)| key == "issuer")
-                .map(|(_, value)| value.to_string())
-                .unwrap_or(label),
-            algo,
-            step: url
-                .query_pairs()
-                .find(|(key, _)| key == "period")
-                .map(|(_, value)| value.to_string())
-                .unwrap_or("30".to_string())
+    fn label(&self) -> Label {
+        Label {
+            issuer: self.issuer.clone(),
+            account: self.account.clone(),
+            warning: None,
+        }
+    }
+
+    fn new(uri: &str, url: &Url, label: Label, id: String) -> Result<TOTP, OtpError> {
+        let step = match query_param(url, "period") {
+            Some(period) => period
                 .parse::<u32>()
-                .unwrap_or(30),
-            full_uri: uri,
-            img_path,
+                .ok()
+                .filter(|step| *step > 0)
+                .ok_or(OtpError::InvalidPeriod { err: period })?,
+            None => 30,
         };
-        Ok(totp)
+        Ok(TOTP {
+            secret: parse_secret(url, &label)?,
+            issuer: label.issuer,
+            account: label.account,
+            algo: parse_algo(url)?,
+            digits: parse_digits(url)?,
+            step,
+            full_uri: uri.to_string(),
+            id,
+            tags: vec![],
+            notes: String::new(),
+        })
+    }
+
+    pub fn to_uri(&self) -> String {
+        format!(
+            "otpauth://totp/{}?secret={}{}&algorithm={}&digits={}&period={}",
+            self.label().to_uri_label(),
+            encode_component(&self.secret),
+            issuer_param(&self.issuer),
+            self.algo.as_str(),
+            self.digits,
+            self.step
+        )
+    }
+}
+
+/// A challenge-response entry, stored as
+/// `otpauth://ocra/<label>?secret=<secret>&suite=<suite>[&counter=<counter>]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OCRA {
+    secret: String,
+    issuer: String,
+    account: String,
+    suite: String,
+    counter: u64,
+    full_uri: String,
+    /// Stable identifier, names the entry's images inside the archive.
+    id: String,
+    /// Free-form words to find the entry by, e.g. `work`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
+}
+
+impl OCRA {
+    fn label(&self) -> Label {
+        Label {
+            issuer: self.issuer.clone(),
+            account: self.account.clone(),
+            warning: None,
+        }
+    }
+
+    fn new(uri: &str, url: &Url, label: Label, id: String) -> Result<OCRA, OtpError> {
+        let suite = query_param(url, "suite").unwrap_or_default();
+        Suite::parse(&suite).map_err(|e| OtpError::InvalidSuite { err: e.to_string() })?;
+        let counter = match query_param(url, "counter") {
+            Some(counter) => counter
+                .parse::<u64>()
+                .map_err(|_| OtpError::InvalidCounter { err: counter })?,
+            None => 0,
+        };
+        Ok(OCRA {
+            secret: parse_secret(url, &label)?,
+            issuer: label.issuer,
+            account: label.account,
+            suite,
+            counter,
+            full_uri: uri.to_string(),
+            id,
+            tags: vec![],
+            notes: String::new(),
+        })
+    }
+
+    pub fn to_uri(&self) -> String {
+        format!(
+            "otpauth://ocra/{}?secret={}{}&suite={}&counter={}",
+            self.label().to_uri_label(),
+            encode_component(&self.secret),
+            issuer_param(&self.issuer),
+            encode_component(&self.suite),
+            self.counter
+        )
+    }
+
+    /// Answers a challenge with the inputs the entry's suite asks for, the
+    /// counter comes from the entry itself.
+    pub fn answer(
+        &self,
+        question: &str,
+        password: Option<&str>,
+        session: Option<&[u8]>,
+        clock: &dyn Clock,
+    ) -> Result<String, ocra::Error> {
+        let suite = Suite::parse(&self.suite)?;
+        let inputs = Inputs {
+            counter: Some(self.counter),
+            question,
+            password,
+            session,
+        };
+        make_ocra(&self.secret, &suite, &inputs, clock)
+    }
+}
+
+fn encode_component(value: &str) -> String {
+    utf8_percent_encode(value, URI_COMPONENT).to_string()
+}
+
+/// The `&issuer=` parameter of a URI, left out when there is no issuer.
+fn issuer_param(issuer: &str) -> String {
+    if issuer.is_empty() {
+        String::new()
+    } else {
+        format!("&issuer={}", encode_component(issuer))
+    }
+}
+
+/// Steam Guard entries are TOTP URIs issued by Steam or explicitly marked
+/// with `encoder=steam`.
+fn is_steam(url: &Url, issuer: &str) -> bool {
+    query_param(url, "encoder").is_some_and(|encoder| encoder.eq_ignore_ascii_case("steam"))
+        || issuer.eq_ignore_ascii_case("steam")
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// The label is the first path segment after "totp/".
+fn raw_label(url: &Url) -> &str {
+    url.path_segments().and_then(|mut segments| segments.next()).unwrap_or_default()
+}
+
+fn parse_secret(url: &Url, label: &Label) -> Result<String, OtpError> {
+    let secret = query_param(url, "secret")
+        .filter(|secret| !secret.trim().is_empty())
+        .ok_or_else(|| OtpError::SecretNotProvided { err: label.to_string() })?;
+    canonical_secret(&secret, SecretEncoding::Base32)
+}
+
+/// How a secret typed in by hand is encoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecretEncoding {
+    Base32,
+    /// Hex, as seeds of hardware tokens are usually distributed.
+    Hex,
+}
+
+/// Turns a secret from a URI or manual entry into the canonical unpadded
+/// upper case base32 form that gets stored.
+pub fn canonical_secret(secret: &str, encoding: SecretEncoding) -> Result<String, OtpError> {
+    match encoding {
+        SecretEncoding::Base32 => normalize_secret(secret),
+        SecretEncoding::Hex => hex_to_secret(secret),
+    }
+    .map_err(|e| OtpError::InvalidSecret { err: e.to_string() })
+}
+
+fn parse_algo(url: &Url) -> Result<Algo, OtpError> {
+    match query_param(url, "algorithm") {
+        Some(algo) => algo.parse(),
+        None => Ok(Algo::SHA1),
+    }
+}
+
+fn parse_digits(url: &Url) -> Result<u32, OtpError> {
+    match query_param(url, "digits") {
+        Some(digits) => digits
+            .parse::<u32>()
+            .ok()
+            .filter(|digits| (1..=10).contains(digits))
+            .ok_or(OtpError::InvalidDigits { err: digits }),
+        None => Ok(6),
     }
-    
 }
 
 pub fn decode_qr(img: &mut DynamicImage) -> Result<String, anyhow::Error> {
     let decoder = bardecoder::default_decoder();
 
     let results = decoder.decode(&img.clone());
-    if results.is_empty() {
-        Err(OtpError::CannotParseQR.into())
-    } else {
-        Ok(results[0].as_ref().clone().unwrap().to_string())
+    match results.into_iter().next() {
+        Some(Ok(uri)) => Ok(uri),
+        _ => Err(OtpError::CannotParseQR.into()),
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-use super::OTP;
+use super::{OtpError, SecretEncoding, OTP};
+use crate::clock::{FixedClock, SystemClock};
 
 
     #[test]
@@ -198,4 +799,219 @@ use super::OTP;
         let list:Vec<OTP> = vec![];
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn code_uses_algorithm() {
+        let otp = OTP::parse_uri("otpauth://hotp/ACME?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA&algorithm=SHA256&counter=1").unwrap();
+        assert_eq!(otp.code(&SystemClock).unwrap(), "119246");
+    }
+
+    #[test]
+    fn steam_detection() {
+        let by_issuer = OTP::parse_uri("otpauth://totp/Steam:john?secret=ON2XAZLSMR2XAZLSONSWG4TFOQ&issuer=Steam").unwrap();
+        assert!(matches!(by_issuer, OTP::Steam(_)));
+        let by_label = OTP::parse_uri("otpauth://totp/Steam:john?secret=ON2XAZLSMR2XAZLSONSWG4TFOQ").unwrap();
+        assert!(matches!(by_label, OTP::Steam(_)));
+        let by_encoder = OTP::parse_uri("otpauth://totp/john?secret=ON2XAZLSMR2XAZLSONSWG4TFOQ&encoder=steam").unwrap();
+        assert!(matches!(by_encoder, OTP::Steam(_)));
+        let plain = OTP::parse_uri("otpauth://totp/ACME:john?secret=ON2XAZLSMR2XAZLSONSWG4TFOQ&issuer=ACME").unwrap();
+        assert!(matches!(plain, OTP::TOTP(_)));
+        assert_eq!(by_encoder.code(&SystemClock).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn ocra() {
+        let otp = OTP::parse_uri("otpauth://ocra/Bank:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&suite=OCRA-1:HOTP-SHA1-6:QN08").unwrap();
+        let OTP::OCRA(ocra) = &otp else {
+            panic!("expected an OCRA entry, got {:?}", otp);
+        };
+        assert_eq!(ocra.answer("11111111", None, None, &SystemClock).unwrap(), "243178");
+        assert!(otp.code(&SystemClock).is_err());
+        assert!(OTP::parse_uri("otpauth://ocra/Bank:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&suite=OCRA-1:HOTP-SHA1-6").is_err());
+    }
+
+    #[test]
+    fn verify() {
+        let mut hotp = OTP::parse_uri("otpauth://hotp/ACME?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0").unwrap();
+        assert!(!hotp.verify("287082", 0, &SystemClock).unwrap().unwrap());
+        assert!(hotp.verify("287082", 1, &SystemClock).unwrap().unwrap());
+        assert_eq!(hotp.to_uri(), "otpauth://hotp/ACME?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&algorithm=SHA1&digits=6&counter=2");
+        assert!(!hotp.verify("287082", 1, &SystemClock).unwrap().unwrap());
+        let mut totp = OTP::parse_uri("otpauth://totp/ACME?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        assert!(totp.verify("287082", 0, &FixedClock(59)).unwrap().unwrap());
+        assert!(totp.verify("287082", 1, &FixedClock(60)).unwrap().unwrap());
+        assert!(!totp.verify("287082", 0, &FixedClock(60)).unwrap().unwrap());
+        let mut steam = OTP::parse_uri("otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam").unwrap();
+        assert!(steam.verify("ABCDE", 1, &FixedClock(0)).is_none());
+    }
+
+    #[test]
+    fn to_uri() {
+        let totp = OTP::parse_uri("otpauth://totp/ACME%20Co:john@example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60").unwrap();
+        assert_eq!(totp.to_uri(), "otpauth://totp/ACME%20Co:john%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60");
+        let hotp = OTP::parse_uri("otpauth://hotp/john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&counter=42").unwrap();
+        assert_eq!(hotp.to_uri(), "otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&counter=42");
+        let steam = OTP::parse_uri("otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam").unwrap();
+        assert_eq!(steam.to_uri(), "otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam&algorithm=SHA1&digits=6&period=30&encoder=steam");
+        let ocra = OTP::parse_uri("otpauth://ocra/Bank:john?secret=JBSWY3DPEHPK3PXP&issuer=Bank&suite=OCRA-1:HOTP-SHA1-6:C-QN08&counter=3").unwrap();
+        assert_eq!(ocra.to_uri(), "otpauth://ocra/Bank:john?secret=JBSWY3DPEHPK3PXP&issuer=Bank&suite=OCRA-1%3AHOTP-SHA1-6%3AC-QN08&counter=3");
+    }
+
+    #[test]
+    fn to_uri_round_trip() {
+        for uri in [
+            "otpauth://totp/ACME%20Co:john@example.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co&algorithm=SHA512&digits=8&period=60",
+            "otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&counter=7",
+            "otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam",
+            "otpauth://ocra/Bank:john?secret=JBSWY3DPEHPK3PXP&issuer=Bank&suite=OCRA-1:HOTP-SHA1-6:QN08",
+        ] {
+            let exported = OTP::parse_uri(uri).unwrap().to_uri();
+            let reimported = OTP::parse_uri(&exported).unwrap();
+            assert_eq!(reimported.to_uri(), exported);
+            assert_eq!(reimported.code(&FixedClock(0)).ok(), OTP::parse_uri(uri).unwrap().code(&FixedClock(0)).ok());
+        }
+    }
+
+    #[test]
+    fn code_uses_digits() {
+        let otp = OTP::parse_uri("otpauth://hotp/ACME?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA&algorithm=SHA256&digits=8&counter=1").unwrap();
+        assert_eq!(otp.code(&SystemClock).unwrap(), "46119246");
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |uri: &str| OTP::parse_uri(uri).unwrap_err();
+        assert!(matches!(parse("not a uri"), OtpError::NotOtpLink { .. }));
+        assert!(matches!(parse("https://totp/ACME?secret=JBSWY3DPEHPK3PXP"), OtpError::NotOtpLink { .. }));
+        assert!(matches!(parse("otpauth://totp/?secret=JBSWY3DPEHPK3PXP"), OtpError::LabelNotProvided { .. }));
+        assert!(matches!(parse("otpauth://motp/ACME?secret=JBSWY3DPEHPK3PXP"), OtpError::CannotIdentifyOtpType { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?issuer=ACME"), OtpError::SecretNotProvided { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?secret=not-base32!"), OtpError::InvalidSecret { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?secret=JBSWY3DPEHPK3PXP&algorithm=MD5"), OtpError::UnknownAlgorithm { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?secret=JBSWY3DPEHPK3PXP&digits=eleven"), OtpError::InvalidDigits { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?secret=JBSWY3DPEHPK3PXP&digits=12"), OtpError::InvalidDigits { .. }));
+        assert!(matches!(parse("otpauth://totp/ACME?secret=JBSWY3DPEHPK3PXP&period=0"), OtpError::InvalidPeriod { .. }));
+        assert!(matches!(parse("otpauth://hotp/ACME?secret=JBSWY3DPEHPK3PXP"), OtpError::CounterNotProvided { .. }));
+        assert!(matches!(parse("otpauth://hotp/ACME?secret=JBSWY3DPEHPK3PXP&counter=-1"), OtpError::InvalidCounter { .. }));
+        assert!(matches!(parse("otpauth://ocra/ACME?secret=JBSWY3DPEHPK3PXP&suite=OCRA-1"), OtpError::InvalidSuite { .. }));
+        for uri in [
+            "otpauth://ocra/Bank:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&suite=OCRA-1%3AHOTP-SHA1-6%3AQN08-",
+            "otpauth://ocra/Bank:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&suite=OCRA-1%3AHOTP-SHA1-6%3AQN08-%C3%A9",
+        ] {
+            assert!(matches!(parse(uri), OtpError::InvalidSuite { .. }), "{uri}");
+        }
+        // errors end up on stderr, they name the entry but never show the secret
+        let err = parse("otpauth://hotp/ACME:john?secret=JBSWY3DPEHPK3PXP").to_string();
+        assert_eq!(err, "HOTP URI didn't contain a counter: ACME:john");
+        for uri in ["otpauth://totp/?secret=JBSWY3DPEHPK3PXP", "otpauth://totp/%20?secret=JBSWY3DPEHPK3PXP"] {
+            assert!(!parse(uri).to_string().contains("JBSWY3DPEHPK3PXP"), "{uri}");
+        }
+    }
+
+    #[test]
+    fn type_from_host() {
+        // "totp" in the label must not turn an HOTP URI into a TOTP one.
+        let otp = OTP::parse_uri("otpauth://hotp/mytotp?secret=JBSWY3DPEHPK3PXP&counter=0").unwrap();
+        assert!(matches!(otp, OTP::HOTP(_)));
+    }
+
+    #[test]
+    fn label() {
+        let otp = OTP::parse_uri("otpauth://totp/ACME%20Co:john@example.com?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(otp.issuer(), "ACME Co");
+        assert_eq!(otp.account(), "john@example.com");
+        assert_eq!(otp.to_string(), "ACME Co (john@example.com)");
+
+        let otp = OTP::parse_uri("otpauth://totp/ACME%20Co%3A%20john?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co").unwrap();
+        assert_eq!(otp.issuer(), "ACME Co");
+        assert_eq!(otp.account(), "john");
+
+        let otp = OTP::parse_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(otp.issuer(), "");
+        assert_eq!(otp.account(), "john");
+        assert_eq!(otp.to_string(), "john");
+        assert_eq!(otp.to_uri(), "otpauth://totp/john?secret=JBSWY3DPEHPK3PXP&algorithm=SHA1&digits=6&period=30");
+
+        // The issuer parameter wins over the label prefix.
+        let uri = "otpauth://totp/Old:john?secret=JBSWY3DPEHPK3PXP&issuer=New";
+        let otp = OTP::parse_uri(uri).unwrap();
+        assert_eq!(otp.issuer(), "New");
+        assert_eq!(otp.account(), "john");
+        assert!(OTP::uri_warning(uri).unwrap().contains("\"Old\" does not match issuer parameter \"New\""));
+        assert_eq!(OTP::uri_warning("otpauth://totp/New:john?secret=JBSWY3DPEHPK3PXP&issuer=New"), None);
+
+        assert!(matches!(
+            OTP::parse_uri("otpauth://totp/ACME:?secret=JBSWY3DPEHPK3PXP"),
+            Err(OtpError::LabelNotProvided { .. })
+        ));
+    }
+
+    #[test]
+    fn tolerant_secrets() {
+        let otp = OTP::parse_uri("otpauth://totp/ACME:john?secret=jbsw%20y3dp%20ehpk%203pxp%3D%3D").unwrap();
+        assert_eq!(otp.to_uri(), "otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME&algorithm=SHA1&digits=6&period=30");
+
+        let manual = OTP::from_manual_entry("ACME", "john", "jbsw-y3dp-ehpk-3pxp", SecretEncoding::Base32).unwrap();
+        assert_eq!(manual.to_uri(), otp.to_uri());
+
+        let hex = OTP::from_manual_entry("ACME", "john", "48656c6c6f21deadbeef", SecretEncoding::Hex).unwrap();
+        assert_eq!(hex.to_uri(), otp.to_uri());
+
+        assert!(matches!(
+            OTP::from_manual_entry("ACME", "john", "not base32!", SecretEncoding::Base32),
+            Err(OtpError::InvalidSecret { .. })
+        ));
+        assert!(matches!(
+            OTP::from_manual_entry("ACME", " ", "JBSWY3DPEHPK3PXP", SecretEncoding::Base32),
+            Err(OtpError::LabelNotProvided { .. })
+        ));
+    }
+
+    #[test]
+    fn editing() {
+        use super::{Algo, Edit};
+
+        let mut otp = OTP::parse_uri("otpauth://totp/ACME:john?secret=JBSWY3DPEHPK3PXP&issuer=ACME").unwrap();
+        let id = otp.id().to_string();
+        otp.edit(Edit {
+            issuer: Some("Globex".to_string()),
+            algo: Some(Algo::SHA256),
+            digits: Some(8),
+            period: Some(60),
+            ..Edit::default()
+        })
+        .unwrap();
+        assert_eq!(otp.to_uri(), "otpauth://totp/Globex:john?secret=JBSWY3DPEHPK3PXP&issuer=Globex&algorithm=SHA256&digits=8&period=60");
+        assert_eq!(otp.id(), id);
+
+        assert!(matches!(otp.edit(Edit { counter: Some(3), ..Edit::default() }), Err(OtpError::NotEditable { .. })));
+        assert!(matches!(otp.edit(Edit { digits: Some(11), ..Edit::default() }), Err(OtpError::InvalidDigits { .. })));
+        assert!(otp.edit(Edit { account: Some(" ".to_string()), issuer: Some("X".to_string()), ..Edit::default() }).is_err());
+        assert_eq!(otp.issuer(), "Globex");
+
+        let tags = vec!["work".to_string(), " ".to_string(), " ci ".to_string()];
+        otp.edit(Edit { tags: Some(tags), notes: Some("Recovery codes in the safe\n".to_string()), ..Edit::default() }).unwrap();
+        assert_eq!(otp.tags(), ["work", "ci"]);
+        assert_eq!(otp.notes(), "Recovery codes in the safe");
+        assert!(!otp.to_uri().contains("work"));
+
+        let mut steam = OTP::parse_uri("otpauth://totp/Steam:john?secret=JBSWY3DPEHPK3PXP&issuer=Steam").unwrap();
+        assert!(steam.edit(Edit { digits: Some(8), ..Edit::default() }).is_err());
+        assert!(!steam.increment_counter());
+    }
+
+    #[test]
+    fn counters() {
+        let mut hotp = OTP::parse_uri("otpauth://hotp/ACME:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0").unwrap();
+        assert_eq!(hotp.code(&SystemClock).unwrap(), "755224");
+        assert!(hotp.increment_counter());
+        assert_eq!(hotp.code(&SystemClock).unwrap(), "287082");
+        assert!(hotp.to_uri().ends_with("&counter=1"));
+
+        let ocra = "otpauth://ocra/Bank:john?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&suite=OCRA-1%3AHOTP-SHA1-6%3AQN08";
+        let mut ocra = OTP::parse_uri(ocra).unwrap();
+        assert_eq!(ocra.answer("00000000", None, None, &SystemClock).unwrap().unwrap(), "237653");
+        assert!(!ocra.increment_counter());
+        assert!(OTP::parse_uri("otpauth://totp/john?secret=JBSWY3DPEHPK3PXP").unwrap().answer("1", None, None, &SystemClock).is_none());
+    }
+}